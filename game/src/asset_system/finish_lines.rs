@@ -1,10 +1,12 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::dynamics::RigidBody;
 use bevy_rapier2d::geometry::{ActiveEvents, Collider, Friction, Sensor};
 use bevy_rapier2d::pipeline::CollisionEvent;
-use crate::score_system::time::TimeText;
+use crate::score_system::time::{RunStartedEvent, TimeText};
+use shared::SPAWN_POSITION;
 
 /// FinsihLine component
 ///
@@ -298,13 +300,13 @@ pub fn finishline_detection(
 /// # Fields
 #[derive(Event)]
 pub struct FinishLineEvent{
-    pub elapsed_time: u64,
+    pub elapsed_time: Duration,
 }
 
 /// Update event on finishline
 ///
 /// this sends an event when the player reaches the finishline, to update the highscore.
-/// sets the elapsed time to 0 and resets the time text.
+/// sets the elapsed time to 0 and resets the time text, which starts a new run.
 /// teleports the player to the start of the level.
 ///
 /// # Arguments
@@ -314,12 +316,14 @@ pub struct FinishLineEvent{
 /// * `finishline_events` - An event writer that writes the finishline events.
 /// * `transforms` - A query that fetches the transform of the entities.
 /// * `time_text` - A query that fetches the time text.
+/// * `run_started_events` - An event writer that writes the run started events.
 pub fn update_on_finishline(
     mut finishline_detectors: Query<&mut FinishLineDetection>,
     finishline_sensors: Query<&FinishLineSensor, Changed<FinishLineSensor>>,
     mut finishline_events: EventWriter<FinishLineEvent>,
     mut transforms: Query<&mut Transform>,
     mut time_text: Query<&mut TimeText, With<TimeText>>,
+    mut run_started_events: EventWriter<RunStartedEvent>,
 ) {
     for sensor in &finishline_sensors {
         if let Ok(mut finishline_detection) = finishline_detectors.get_mut(sensor.finishline_detection_entity) {
@@ -327,10 +331,11 @@ pub fn update_on_finishline(
             if finishline_detection.on_finishline {
                 if let Ok(mut transform) = transforms.get_mut(sensor.finishline_detection_entity) {
                     let mut time_text = time_text.single_mut();
-                    let elapsed_time = time_text.time.elapsed().as_secs();
+                    let elapsed_time = time_text.time.elapsed();
                     finishline_events.send(FinishLineEvent{elapsed_time});
-                    transform.translation = Vec2::new(40., 40.).extend(0.0);
+                    transform.translation = Vec2::from_array(SPAWN_POSITION).extend(0.0);
                     time_text.time.reset();
+                    run_started_events.send(RunStartedEvent);
                 }
             }
         }
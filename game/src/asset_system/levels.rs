@@ -0,0 +1,31 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+/// Resource storing the iid of the level that is currently played
+///
+/// The iid is the identifier LDtk gives every level. It is used to tell the server
+/// which level a highscore belongs to. `None` until the first level has spawned.
+#[derive(Resource, Default)]
+pub struct CurrentLevel {
+    pub iid: Option<String>,
+}
+
+/// Updates the current level
+///
+/// This function listens for level events of the ldtk plugin and stores the iid of the level
+/// that spawned last in the `CurrentLevel` resource.
+///
+/// # Arguments
+///
+/// * `level_events` - An event reader that reads the level events.
+/// * `current_level` - A mutable reference to the `CurrentLevel` resource.
+pub fn update_current_level(
+    mut level_events: EventReader<LevelEvent>,
+    mut current_level: ResMut<CurrentLevel>,
+) {
+    for level_event in level_events.read() {
+        if let LevelEvent::Spawned(level_iid) = level_event {
+            current_level.iid = Some(level_iid.to_string());
+        }
+    }
+}
@@ -2,6 +2,7 @@
 pub mod assets_loading;
 pub mod collision;
 pub mod ghost_physics;
+pub mod levels;
 pub mod players;
 pub mod walls;
 pub mod traps;
@@ -4,7 +4,8 @@ use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::dynamics::RigidBody;
 use bevy_rapier2d::geometry::{ActiveEvents, Collider, Friction, Sensor};
 use bevy_rapier2d::pipeline::CollisionEvent;
-use crate::score_system::time::TimeText;
+use crate::score_system::time::{RunStartedEvent, TimeText};
+use shared::SPAWN_POSITION;
 
 /// Component for traps
 #[derive(Default, Component)]
@@ -300,6 +301,7 @@ pub fn trap_detection(
 /// Updates the trap detection
 ///
 /// this function teleports the player to the beginning of the level and resets the timer if the player hits on a trap.
+/// the reset starts a new run, which is reported to the server.
 ///
 /// # Arguments
 ///
@@ -307,11 +309,13 @@ pub fn trap_detection(
 /// * `trap_sensors` - A query that gets the trap sensor component.
 /// * `transforms` - A query that gets the transform component.
 /// * `time_text` - A query that gets the time text component.
+/// * `run_started_events` - An event writer for the run started events.
 pub fn update_on_trap(
     mut trap_detectors: Query<&mut TrapDetection>,
     trap_sensors: Query<&TrapSensor, Changed<TrapSensor>>,
     mut transforms: Query<&mut Transform>,
     mut time_text: Query<&mut TimeText, With<TimeText>>,
+    mut run_started_events: EventWriter<RunStartedEvent>,
 ) {
     for sensor in &trap_sensors {
         if let Ok(mut trap_detection) = trap_detectors.get_mut(sensor.trap_detection_entity) {
@@ -319,9 +323,10 @@ pub fn update_on_trap(
             if trap_detection.on_trap {
                 if let Ok(mut transform) = transforms.get_mut(sensor.trap_detection_entity) {
                     // Set the new position for the entity
-                    transform.translation = Vec2::new(40., 40.).extend(0.0);
+                    transform.translation = Vec2::from_array(SPAWN_POSITION).extend(0.0);
                     let mut time_text = time_text.single_mut();
                     time_text.time.reset();
+                    run_started_events.send(RunStartedEvent);
                 }
             }
         }
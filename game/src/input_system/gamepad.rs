@@ -49,17 +49,14 @@ pub fn gamepad_connections(
         if let GamepadEvent::Connection(connection_event) = ev {
             let id = connection_event.gamepad;
             if let GamepadConnection::Connected(info) = &connection_event.connection {
-                println!(
-                    "New gamepad connected with ID: {:?}, name: {}",
-                    id, info.name
-                );
+                info!(gamepad = ?id, name = %info.name, "New gamepad connected.");
 
                 // if we don't have any gamepad yet, use this one
                 if my_gamepad.is_none() {
                     commands.insert_resource(MyGamepad(id));
                 }
             } else {
-                println!("Lost gamepad connection with ID: {:?}", id);
+                info!(gamepad = ?id, "Lost gamepad connection.");
 
                 // if it's the one we previously associated with the player,
                 // disassociate it:
@@ -112,7 +109,7 @@ pub fn gamepad_input(
                         //add small deadzones
                         if f32::abs(axis_changed.value) > DEADZONE {
                             handler.walking = axis_changed.value;
-                            println!("Joystick moved on X Axis");
+                            trace!("Joystick moved on X Axis");
                         } else {
                             //joystick position reset to zero
                             handler.walking = 0.0;
@@ -121,7 +118,7 @@ pub fn gamepad_input(
                     GamepadAxisType::LeftStickY => {
                         if f32::abs(axis_changed.value) > DEADZONE {
                             //joystick moved beyond Deadzone
-                            println!("Joystick moved on Y Axis");
+                            trace!("Joystick moved on Y Axis");
                         } else {
                             //joystick position reset to zero
                         }
@@ -1,7 +1,10 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use bevy::prelude::*;
+use std::{env, path::PathBuf};
+
+use bevy::{log::LogPlugin, prelude::*};
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
+use shared::logging::{self, LogConfig};
 
 mod asset_system;
 mod input_system;
@@ -9,7 +12,20 @@ mod movement_system;
 mod multiplayer_system;
 mod score_system;
 
+/// Command line argument and environment variable to set the format of the log, `pretty` or `json`.
+const LOG_FORMAT_ARG: &str = "--log-format";
+const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+
+/// Command line argument and environment variable to write the log to a file instead of stderr.
+const LOG_FILE_ARG: &str = "--log-file";
+const LOG_FILE_ENV: &str = "LOG_FILE";
+
 fn main() {
+    // Logging is set up before bevy, its `LogPlugin` is replaced by `shared::logging`
+    if let Err(error) = logging::init_logging(&log_config()) {
+        eprintln!("Failed to set up logging: {}", error);
+    }
+
     let mut app = App::new();
     app.add_plugins(
         DefaultPlugins
@@ -20,7 +36,8 @@ fn main() {
                     ..Default::default()
                 }),
                 ..Default::default()
-            }),
+            })
+            .disable::<LogPlugin>(),
     );
     app.add_plugins((
         LdtkPlugin,
@@ -34,6 +51,7 @@ fn main() {
         ..Default::default()
     });
     app.insert_resource(LevelSelection::index(0));
+    app.init_resource::<asset_system::levels::CurrentLevel>();
     app.insert_resource(LdtkSettings {
         level_background: LevelBackground::Nonexistent,
         ..Default::default()
@@ -44,6 +62,9 @@ fn main() {
             asset_system::assets_loading::setup,
             score_system::time::setup,
             score_system::highscore_label::setup,
+            score_system::leaderboard::setup,
+            score_system::connection_label::setup,
+            score_system::race_label::setup,
         ),
     );
 
@@ -51,8 +72,16 @@ fn main() {
         Update,
         (
             input_system::gamepad::gamepad_connections,
-            input_system::gamepad::gamepad_input,
-            input_system::keyboard::keyboard_input,
+            input_system::gamepad::gamepad_input
+                .run_if(multiplayer_system::connect_screen::is_connect_screen_closed)
+                .run_if(multiplayer_system::room_screen::is_room_screen_closed)
+                .run_if(multiplayer_system::chat::is_chat_closed)
+                .run_if(multiplayer_system::race::is_input_unlocked),
+            input_system::keyboard::keyboard_input
+                .run_if(multiplayer_system::connect_screen::is_connect_screen_closed)
+                .run_if(multiplayer_system::room_screen::is_room_screen_closed)
+                .run_if(multiplayer_system::chat::is_chat_closed)
+                .run_if(multiplayer_system::race::is_input_unlocked),
             movement_system::player_movement::player_movement,
             asset_system::walls::spawn_wall_collision,
             asset_system::walls::spawn_ground_sensor,
@@ -66,17 +95,52 @@ fn main() {
             asset_system::finish_lines::spawn_finishline_sensor,
             asset_system::finish_lines::finishline_detection,
             asset_system::finish_lines::update_on_finishline,
+            asset_system::levels::update_current_level,
             score_system::time::change_time_text,
             score_system::highscore_label::update_highscore,
             movement_system::camera_movement::camera_movement,
         ),
     );
-    app.register_ldtk_entity::<asset_system::players::PlayerBundle>("Player");
+    app.add_systems(
+        Update,
+        (
+            score_system::leaderboard::toggle_leaderboard,
+            score_system::leaderboard::update_leaderboard,
+            score_system::time::update_last_run,
+            score_system::connection_label::update_connection_label,
+            score_system::race_label::update_race_label,
+        ),
+    );
+    // Spectators watch the other players without a player of their own
+    if !app.world.resource::<multiplayer_system::spectator::SpectatorMode>().0 {
+        app.register_ldtk_entity::<asset_system::players::PlayerBundle>("Player");
+    }
     app.register_ldtk_int_cell_for_layer::<asset_system::walls::WallBundle>("Map_IntGrid",1);
     app.register_ldtk_int_cell_for_layer::<asset_system::traps::TrapBundle>("Traps_IntGrid", 1);
     app.register_ldtk_int_cell_for_layer::<asset_system::finish_lines::FinishLineBundle>("Finish_Line_IntGrid", 1);
 
     app.add_event::<asset_system::finish_lines::FinishLineEvent>();
+    app.add_event::<score_system::time::RunStartedEvent>();
+    app.add_event::<score_system::leaderboard::LeaderboardOpenedEvent>();
 
     app.run();
-}
\ No newline at end of file
+}
+
+/// Reads the format and file of the log from the command line arguments or the environment variables,
+/// in this order. Falls back to pretty text on stderr.
+fn log_config() -> LogConfig {
+    let arg = |name: &str| env::args().skip_while(|arg| arg != name).nth(1);
+
+    let format = match arg(LOG_FORMAT_ARG).or_else(|| env::var(LOG_FORMAT_ENV).ok()) {
+        Some(format) => format.parse().unwrap_or_else(|error| {
+            eprintln!("{}, using pretty text instead.", error);
+            Default::default()
+        }),
+        None => Default::default(),
+    };
+    let file = arg(LOG_FILE_ARG)
+        .or_else(|| env::var(LOG_FILE_ENV).ok())
+        .map(PathBuf::from);
+
+    LogConfig { format, file }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashMap, env};
+
+use bevy::prelude::*;
+use bevy_quinnet::{
+    client::certificate::{
+        CertConnectionAbortEvent, CertInteractionEvent, CertTrustUpdateEvent, CertVerificationStatus,
+        CertVerifierAction, CertVerifierBehaviour, CertificateVerificationMode, KnownHosts,
+        TrustOnFirstUseConfig,
+    },
+    shared::CertificateFingerprint,
+};
+
+use crate::multiplayer_system::connect_screen::ConnectScreenState;
+use crate::multiplayer_system::server_address::ServerAddress;
+
+/// Environment variable to pin the fingerprint of the server certificate.
+const SERVER_FINGERPRINT_ENV: &str = "SERVER_FINGERPRINT";
+
+/// Command line argument to pin the fingerprint of the server certificate, e.g. `--server-fingerprint <base64>`.
+/// The server prints its fingerprint at startup.
+const SERVER_FINGERPRINT_ARG: &str = "--server-fingerprint";
+
+/// Environment variable to overwrite the path of the known hosts file.
+const KNOWN_HOSTS_FILE_ENV: &str = "KNOWN_HOSTS_FILE";
+
+/// Path of the known hosts file used when [`KNOWN_HOSTS_FILE_ENV`] is not set.
+const DEFAULT_KNOWN_HOSTS_FILE: &str = "known_hosts";
+
+/// Length in bytes of a certificate fingerprint, a SHA-256 hash.
+const FINGERPRINT_LENGTH: usize = 32;
+
+/// How the certificate of the server is verified.
+#[derive(Resource)]
+pub enum CertificateTrust {
+    /// Trusts the certificate of a server on the first connection and stores its fingerprint in the
+    /// known hosts file. Connections to a known server whose fingerprint changed are aborted.
+    TrustOnFirstUse { known_hosts_file: String },
+    /// Only accepts a server with exactly this fingerprint.
+    Pinned(CertificateFingerprint),
+}
+
+impl CertificateTrust {
+    /// Uses the fingerprint from the `--server-fingerprint` command line argument or the `SERVER_FINGERPRINT`
+    /// environment variable if set, otherwise trust on first use with the known hosts file.
+    pub fn from_args_or_env() -> Self {
+        let mut args = env::args()
+            .skip_while(|arg| arg != SERVER_FINGERPRINT_ARG)
+            .skip(1);
+        let pinned = args.next().or_else(|| env::var(SERVER_FINGERPRINT_ENV).ok());
+
+        if let Some(pinned) = pinned {
+            match parse_fingerprint(&pinned) {
+                Some(fingerprint) => return CertificateTrust::Pinned(fingerprint),
+                None => warn!(
+                    "Ignoring invalid server fingerprint `{}`, using trust on first use instead.",
+                    pinned
+                ),
+            }
+        }
+
+        let known_hosts_file = env::var(KNOWN_HOSTS_FILE_ENV)
+            .unwrap_or_else(|_| DEFAULT_KNOWN_HOSTS_FILE.to_string());
+        CertificateTrust::TrustOnFirstUse { known_hosts_file }
+    }
+
+    /// Creates the verification mode used to connect to the server.
+    pub fn verification_mode(&self) -> CertificateVerificationMode {
+        let abort = || CertVerifierBehaviour::ImmediateAction(CertVerifierAction::AbortConnection);
+
+        let (known_hosts, unknown_behaviour) = match self {
+            CertificateTrust::TrustOnFirstUse { known_hosts_file } => (
+                KnownHosts::HostsFile(known_hosts_file.clone()),
+                CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustAndStore),
+            ),
+            // The known hosts of the ``bevy_quinnet`` library are keyed by server names which can not be
+            // created outside of it, so the pinned fingerprint is compared in `on_certificate_interaction`.
+            CertificateTrust::Pinned(_) => (
+                KnownHosts::Store(HashMap::new()),
+                CertVerifierBehaviour::RequestClientAction,
+            ),
+        };
+
+        CertificateVerificationMode::TrustOnFirstUse(TrustOnFirstUseConfig {
+            known_hosts,
+            verifier_behaviour: HashMap::from([
+                (
+                    CertVerificationStatus::TrustedCertificate,
+                    CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustOnce),
+                ),
+                (CertVerificationStatus::UnknownCertificate, unknown_behaviour),
+                (CertVerificationStatus::UntrustedCertificate, abort()),
+            ]),
+        })
+    }
+}
+
+/// Decodes a fingerprint as printed by the server, the base64 encoded SHA-256 hash of its certificate.
+/// Returns `None` if the input is no valid base64 or does not decode to [`FINGERPRINT_LENGTH`] bytes.
+fn parse_fingerprint(input: &str) -> Option<CertificateFingerprint> {
+    let bytes: [u8; FINGERPRINT_LENGTH] = decode_base64(input.trim())?.try_into().ok()?;
+    Some(CertificateFingerprint::new(bytes))
+}
+
+/// Decodes standard base64 with optional padding, as written by the ``bevy_quinnet`` library.
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Called when the certificate of a new server was trusted and stored in the known hosts file.
+pub fn on_certificate_trusted(
+    mut events: EventReader<CertTrustUpdateEvent>,
+    server_address: Res<ServerAddress>,
+) {
+    for ev in events.read() {
+        info!(
+            server = %server_address.input,
+            fingerprint = %ev.cert_info.fingerprint.to_base64(),
+            "Trusting the certificate of the server from now on."
+        );
+    }
+}
+
+/// Called when the certificate of the server has to be checked against the pinned fingerprint.
+/// Continues the connection only if the fingerprint matches, otherwise it is aborted.
+pub fn on_certificate_interaction(
+    mut events: EventReader<CertInteractionEvent>,
+    trust: Res<CertificateTrust>,
+) {
+    for ev in events.read() {
+        let action = match &*trust {
+            CertificateTrust::Pinned(fingerprint) if *fingerprint == ev.info.fingerprint => {
+                CertVerifierAction::TrustOnce
+            }
+            _ => CertVerifierAction::AbortConnection,
+        };
+        if let Err(error) = ev.apply_cert_verifier_action(action) {
+            warn!(%error, "Failed to verify the certificate of the server.");
+        }
+    }
+}
+
+/// Called when the connection was aborted because the certificate of the server is not trusted.
+/// Warns the player on the connect screen, as this means the server changed its certificate or
+/// someone is impersonating it.
+pub fn on_certificate_rejected(
+    mut events: EventReader<CertConnectionAbortEvent>,
+    trust: Res<CertificateTrust>,
+    server_address: Res<ServerAddress>,
+    mut connect_screen: ResMut<ConnectScreenState>,
+) {
+    for ev in events.read() {
+        let hint = match &*trust {
+            CertificateTrust::TrustOnFirstUse { known_hosts_file } => format!(
+                "If the server got a new certificate, remove it from {}.",
+                known_hosts_file
+            ),
+            CertificateTrust::Pinned(_) => "It does not match the pinned fingerprint.".to_string(),
+        };
+        let warning = format!(
+            "Warning: the certificate of {} changed!\nFingerprint: {}\n{}",
+            server_address.input,
+            ev.cert_info.fingerprint.to_base64(),
+            hint
+        );
+        warn!("{}", warning);
+
+        connect_screen.open = true;
+        connect_screen.input = server_address.input.clone();
+        connect_screen.error = Some(warning);
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_pinned_fingerprint_is_parsed() {
+    let fingerprint = CertificateFingerprint::new([7; FINGERPRINT_LENGTH]);
+    assert_eq!(parse_fingerprint(&fingerprint.to_base64()), Some(fingerprint));
+
+    // Not base64 and too short for a SHA-256 hash
+    assert_eq!(parse_fingerprint("not base64!"), None);
+    assert_eq!(parse_fingerprint("AAECAwQFBgcICQoLDA0ODw=="), None);
+}
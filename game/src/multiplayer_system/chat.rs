@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use bevy::time::Real;
+use bevy::window::ReceivedCharacter;
+use bevy_quinnet::client::Client;
+use shared::{ChatMessage, PlayerMessage, ShutdownInfo};
+
+use crate::asset_system::players::Player;
+use crate::input_system::input_handler::InputHandler;
+
+/// Key used to open the chat input.
+const OPEN_BUTTON: KeyCode = KeyCode::T;
+
+/// Maximum number of characters of a typed message. The server rejects longer ones.
+const MAX_INPUT_LENGTH: usize = 200;
+
+/// Number of chat lines kept and shown.
+const MAX_LINES: usize = 8;
+
+/// How long a chat line stays visible while the chat input is closed.
+const LINE_DISPLAY_DURATION: Duration = Duration::from_secs(15);
+
+/// This component marks the text of the chat overlay.
+#[derive(Component)]
+pub struct ChatText;
+
+/// State of the chat.
+///
+/// # Fields
+///
+/// * `open` - If the player is currently typing a message.
+/// * `input` - The message typed by the player.
+/// * `lines` - The last received messages together with the real time of the game they arrived at.
+#[derive(Resource, Default)]
+pub struct ChatState {
+    pub open: bool,
+    pub input: String,
+    pub lines: VecDeque<(Duration, String)>,
+}
+
+/// Bevy event to be fired when the server forwards a chat message of a player in the room.
+#[derive(Event)]
+pub struct ChatReceivedEvent(pub ChatMessage);
+
+/// Bevy event to be fired when the server did not forward a message of the player. Contains the reason.
+#[derive(Event)]
+pub struct ChatRejectedEvent(pub String);
+
+/// Bevy event to be fired when the server operator sent a message to all players.
+#[derive(Event)]
+pub struct AnnouncementEvent(pub String);
+
+/// Bevy event to be fired when the server is about to stop. The game reconnects on its own afterwards.
+#[derive(Event)]
+pub struct ServerShuttingDownEvent(pub ShutdownInfo);
+
+/// Sets up the chat overlay
+///
+/// This function is responsible for spawning the entity of the chat overlay in the ECS.
+/// It spawns an empty `TextBundle` entity in the lower left corner of the window with a `ChatText` component.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Pixelfont.ttf"),
+                font_size: 20.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(20.0),
+            max_width: Val::Percent(50.0),
+            ..default()
+        }),
+        ChatText,
+    ));
+}
+
+/// Opens and closes the chat input
+///
+/// This function opens the chat input when the open key is pressed. The input is closed again with escape,
+/// which throws away the typed message. Keys held while the chat opens are released, so the player
+/// does not keep moving while typing.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `state` - The `ChatState` resource.
+/// * `player` - The input handler of the player.
+pub fn toggle_chat(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<ChatState>,
+    mut player: Query<&mut InputHandler, With<Player>>,
+) {
+    if !state.open && keyboard_input.just_pressed(OPEN_BUTTON) {
+        state.open = true;
+        state.input.clear();
+        for mut input_handler in player.iter_mut() {
+            *input_handler = InputHandler::default();
+        }
+    } else if state.open && keyboard_input.just_pressed(KeyCode::Escape) {
+        state.open = false;
+    }
+}
+
+/// Handles typing a chat message
+///
+/// This function appends the typed characters to the message, removes the last one on backspace and sends
+/// the message to the server on enter. Has to run before [`toggle_chat`], so the key opening the chat is
+/// not typed into the message.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `characters` - An `EventReader` for the characters typed by the player.
+/// * `state` - The `ChatState` resource.
+/// * `client` - The client used to send the message.
+pub fn type_chat_message(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut state: ResMut<ChatState>,
+    client: Res<Client>,
+) {
+    if !state.open {
+        characters.clear();
+        return;
+    }
+
+    for ev in characters.read() {
+        if !ev.char.is_control() && state.input.chars().count() < MAX_INPUT_LENGTH {
+            state.input.push(ev.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        state.input.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        let message = std::mem::take(&mut state.input);
+        if !message.trim().is_empty() {
+            if let Some(connection) = client.get_connection() {
+                connection.try_send_message(PlayerMessage::Chat(message));
+            }
+        }
+        state.open = false;
+    }
+}
+
+/// Adds the messages forwarded by the server, the announcements of the server operator, the reasons of
+/// rejected messages and the shutdown of the server to the chat lines.
+pub fn on_chat_messages(
+    time: Res<Time<Real>>,
+    mut received_events: EventReader<ChatReceivedEvent>,
+    mut rejected_events: EventReader<ChatRejectedEvent>,
+    mut announcement_events: EventReader<AnnouncementEvent>,
+    mut shutdown_events: EventReader<ServerShuttingDownEvent>,
+    mut state: ResMut<ChatState>,
+) {
+    let received = received_events
+        .read()
+        .map(|ev| format!("{}: {}", ev.0.player_name, ev.0.text));
+    let rejected = rejected_events.read().map(|ev| format!("({})", ev.0));
+    let announcements = announcement_events.read().map(|ev| format!("[Server] {}", ev.0));
+    let shutdowns = shutdown_events.read().map(|ev| match ev.0.restart_in {
+        Some(restart_in) => format!(
+            "[Server] {} Back in about {} seconds.",
+            ev.0.reason,
+            restart_in.as_secs()
+        ),
+        None => format!("[Server] {}", ev.0.reason),
+    });
+    let lines: Vec<String> = received
+        .chain(rejected)
+        .chain(announcements)
+        .chain(shutdowns)
+        .collect();
+
+    for line in lines {
+        state.lines.push_back((time.elapsed(), line));
+        if state.lines.len() > MAX_LINES {
+            state.lines.pop_front();
+        }
+    }
+}
+
+/// Updates the chat overlay
+///
+/// This function shows the recent chat lines and, while the chat input is open, all kept lines and the typed message.
+///
+/// # Arguments
+///
+/// * `time` - The real time of the game, used to hide old lines.
+/// * `state` - The `ChatState` resource.
+/// * `query` - A `Query` that fetches the `Text` component of the chat overlay.
+pub fn update_chat_overlay(
+    time: Res<Time<Real>>,
+    state: Res<ChatState>,
+    mut query: Query<&mut Text, With<ChatText>>,
+) {
+    let now = time.elapsed();
+    let mut lines: Vec<&str> = state
+        .lines
+        .iter()
+        .filter(|(received_at, _)| state.open || now < *received_at + LINE_DISPLAY_DURATION)
+        .map(|(_, line)| line.as_str())
+        .collect();
+    let input = format!("> {}_", state.input);
+    if state.open {
+        lines.push(&input);
+    }
+    let value = lines.join("\n");
+
+    for mut text in query.iter_mut() {
+        // Only touch the text when it changed, so it is not laid out again every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+/// Run condition which is true while the chat input is closed. Used to ignore game input while typing.
+pub fn is_chat_closed(state: Res<ChatState>) -> bool {
+    !state.open
+}
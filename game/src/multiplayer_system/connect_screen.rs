@@ -0,0 +1,197 @@
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+
+use crate::asset_system::players::Player;
+use crate::input_system::input_handler::InputHandler;
+use crate::multiplayer_system::server_address::ServerAddress;
+
+/// Key used to open the connect screen.
+const OPEN_BUTTON: KeyCode = KeyCode::F2;
+
+/// Maximum number of characters of the typed address.
+const MAX_INPUT_LENGTH: usize = 64;
+
+/// This component marks the root node of the connect screen.
+#[derive(Component)]
+pub struct ConnectScreen;
+
+/// This component marks the text showing the typed address.
+#[derive(Component)]
+pub struct ConnectScreenText;
+
+/// State of the connect screen.
+///
+/// # Fields
+///
+/// * `open` - If the connect screen is currently shown.
+/// * `input` - The address typed by the player.
+/// * `error` - Why the last typed address could not be used.
+#[derive(Resource, Default)]
+pub struct ConnectScreenState {
+    pub open: bool,
+    pub input: String,
+    pub error: Option<String>,
+}
+
+/// Bevy event to be fired when the player entered a valid server address on the connect screen.
+#[derive(Event)]
+pub struct ConnectRequestedEvent(pub ServerAddress);
+
+/// Sets up the connect screen
+///
+/// This function is responsible for spawning the entities of the connect screen in the ECS.
+/// It spawns a hidden `NodeBundle` entity with a `ConnectScreen` component that covers the center of the window,
+/// and a `TextBundle` child with a `ConnectScreenText` component that shows the typed address.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(30.0),
+                    left: Val::Percent(20.0),
+                    width: Val::Percent(60.0),
+                    padding: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ConnectScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/Pixelfont.ttf"),
+                        font_size: 30.0,
+                        ..default()
+                    },
+                ),
+                ConnectScreenText,
+            ));
+        });
+}
+
+/// Opens and closes the connect screen
+///
+/// This function opens the connect screen when the open key is pressed and fills in the current server address.
+/// The screen is closed again with escape. Keys held while the screen opens are released.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `state` - The `ConnectScreenState` resource.
+/// * `server_address` - The address of the server the client is connected to.
+/// * `player` - The input handler of the player.
+pub fn toggle_connect_screen(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<ConnectScreenState>,
+    server_address: Res<ServerAddress>,
+    mut player: Query<&mut InputHandler, With<Player>>,
+) {
+    if !state.open && keyboard_input.just_pressed(OPEN_BUTTON) {
+        state.open = true;
+        state.input = server_address.input.clone();
+        state.error = None;
+        for mut input_handler in player.iter_mut() {
+            *input_handler = InputHandler::default();
+        }
+    } else if state.open && keyboard_input.just_pressed(KeyCode::Escape) {
+        state.open = false;
+    }
+}
+
+/// Handles typing on the connect screen
+///
+/// This function appends the typed characters to the address, removes the last one on backspace
+/// and sends a `ConnectRequestedEvent` on enter if the address is valid. The address is remembered
+/// in the settings file, so it is used again the next time the game starts.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `characters` - An `EventReader` for the characters typed by the player.
+/// * `state` - The `ConnectScreenState` resource.
+/// * `connect_events` - An `EventWriter` for `ConnectRequestedEvent` events.
+pub fn type_server_address(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut state: ResMut<ConnectScreenState>,
+    mut connect_events: EventWriter<ConnectRequestedEvent>,
+) {
+    if !state.open {
+        characters.clear();
+        return;
+    }
+
+    for ev in characters.read() {
+        let allowed = ev.char.is_ascii_alphanumeric() || ".:-[]".contains(ev.char);
+        if allowed && state.input.len() < MAX_INPUT_LENGTH {
+            state.input.push(ev.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        state.input.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        match ServerAddress::parse(&state.input) {
+            Ok(server_address) => {
+                server_address.remember();
+                connect_events.send(ConnectRequestedEvent(server_address));
+                state.open = false;
+            }
+            Err(error) => state.error = Some(error),
+        }
+    }
+}
+
+/// Updates the connect screen
+///
+/// This function shows or hides the connect screen and updates its `Text` component whenever the
+/// `ConnectScreenState` changed.
+///
+/// # Arguments
+///
+/// * `state` - The `ConnectScreenState` resource.
+/// * `screens` - A `Query` that fetches the `Visibility` component of the connect screen.
+/// * `query` - A `Query` that fetches the `Text` component of the connect screen text entity.
+pub fn update_connect_screen(
+    state: Res<ConnectScreenState>,
+    mut screens: Query<&mut Visibility, With<ConnectScreen>>,
+    mut query: Query<&mut Text, With<ConnectScreenText>>,
+) {
+    if !state.is_changed() {
+        return;
+    }
+
+    for mut visibility in screens.iter_mut() {
+        *visibility = if state.open {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    let hint = state
+        .error
+        .clone()
+        .unwrap_or_else(|| "Enter: connect   Esc: cancel".to_string());
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Connect to server\n\n> {}_\n\n{}", state.input, hint);
+    }
+}
+
+/// Run condition which is true while the connect screen is closed. Used to ignore game input while typing.
+pub fn is_connect_screen_closed(state: Res<ConnectScreenState>) -> bool {
+    !state.open
+}
@@ -4,46 +4,138 @@ use bevy::{
     ecs::{
         event::{EventReader, EventWriter},
         schedule::IntoSystemConfigs,
-        system::{Res, ResMut},
+        system::{Res, ResMut, Resource, SystemParam},
     },
+    log::{error, info},
     time::{Timer, TimerMode},
 };
 use bevy_quinnet::client::{
-    certificate::CertificateVerificationMode,
     connection::{ConnectionConfiguration, ConnectionEvent, ConnectionLostEvent},
     Client, QuinnetClientPlugin,
 };
 
 
+use crate::multiplayer_system::certificate;
+use crate::multiplayer_system::certificate::CertificateTrust;
+use crate::multiplayer_system::chat;
+use crate::multiplayer_system::chat::{
+    AnnouncementEvent, ChatReceivedEvent, ChatRejectedEvent, ChatState, ServerShuttingDownEvent,
+};
+use crate::multiplayer_system::connect_screen;
+use crate::multiplayer_system::connect_screen::{ConnectRequestedEvent, ConnectScreenState};
 use crate::multiplayer_system::ghost_player;
-use crate::multiplayer_system::ghost_player::GhostPlayersMovedEvent;
+use crate::multiplayer_system::ghost_player::{GhostPlayersMovedEvent, SnapshotHistory};
+use crate::multiplayer_system::handshake;
+use crate::multiplayer_system::handshake::{
+    HandshakeAcceptedEvent, HandshakeRejectedEvent, JoinRejectedEvent, KickedEvent,
+};
 use crate::multiplayer_system::highscore;
-use crate::multiplayer_system::highscore::HighscoreInfoEvent;
+use crate::multiplayer_system::highscore::{
+    HighscoreInfoEvent, HighscoreRemovedEvent, RunTimeConfirmedEvent,
+};
+use crate::multiplayer_system::interpolation;
+use crate::multiplayer_system::interpolation::{InterpolationSettings, ServerClock};
+use crate::multiplayer_system::latency;
+use crate::multiplayer_system::latency::{Latency, PingTimer, PongReceivedEvent};
+use crate::multiplayer_system::leaderboard;
+use crate::multiplayer_system::leaderboard::LeaderboardInfoEvent;
 use crate::multiplayer_system::player_movement;
-use shared::{PlayerMessage, PlayerMovement, ServerMessage};
-
-/// The ip adress of the server. Use `127.0.0.1` when running the server locally, otherwise replace it
-/// with the ip of your hosted server.
-const SERVER_IP_ADDR: &'static str = "127.0.0.1";
-
-/// Port the client should connect to on the server.
-const SERVER_PORT: u16 = 8123;
+use crate::multiplayer_system::race;
+use crate::multiplayer_system::race::{
+    RaceCountdownEvent, RaceReadyEvent, RaceResultsEvent, RaceState,
+};
+use crate::multiplayer_system::reconnect;
+use crate::multiplayer_system::reconnect::{JoinAcceptedEvent, ReconnectState, SessionToken};
+use crate::multiplayer_system::room_screen;
+use crate::multiplayer_system::room_screen::{
+    RoomJoinedEvent, RoomListEvent, RoomRejectedEvent, RoomScreenState,
+};
+use crate::multiplayer_system::roster;
+use crate::multiplayer_system::roster::{PlayerName, Roster, RosterUpdatedEvent};
+use crate::multiplayer_system::server_address::ServerAddress;
+use crate::multiplayer_system::spectator;
+use crate::multiplayer_system::spectator::{SpectatorCamera, SpectatorMode};
+use shared::{PlayerMessage, ServerMessage};
 
 /// Local address and port to bind to. See [`std::net::SocketAddrV4`] for more information.
 const LOCAL_BIND_ADDR: &'static str = "0.0.0.0:0";
 
+/// State of the connection to the server, shown in the HUD.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectionStatus {
+    /// The connection to the server is being opened.
+    #[default]
+    Connecting,
+    /// Connected to the server.
+    Connected,
+    /// The connection was lost and the next reconnect attempt is scheduled.
+    Disconnected,
+    /// Not connected and not trying to connect, e.g. because the server rejected the game.
+    /// The game can still be played alone.
+    Offline,
+}
+
 /// Adds all necessary plugins, resources and systems to the app to use multiplayer functionality.
 pub fn setup_client(app: &mut App) {
     app.add_plugins(QuinnetClientPlugin::default());
 
     app.add_event::<HighscoreInfoEvent>();
+    app.add_event::<HighscoreRemovedEvent>();
+    app.add_event::<RunTimeConfirmedEvent>();
     app.add_event::<GhostPlayersMovedEvent>();
+    app.add_event::<LeaderboardInfoEvent>();
+    app.add_event::<RosterUpdatedEvent>();
+    app.add_event::<ConnectRequestedEvent>();
+    app.add_event::<HandshakeAcceptedEvent>();
+    app.add_event::<HandshakeRejectedEvent>();
+    app.add_event::<KickedEvent>();
+    app.add_event::<JoinRejectedEvent>();
+    app.add_event::<JoinAcceptedEvent>();
+    app.add_event::<PongReceivedEvent>();
+    app.add_event::<RoomListEvent>();
+    app.add_event::<RoomJoinedEvent>();
+    app.add_event::<RoomRejectedEvent>();
+    app.add_event::<RaceReadyEvent>();
+    app.add_event::<RaceCountdownEvent>();
+    app.add_event::<RaceResultsEvent>();
+    app.add_event::<ChatReceivedEvent>();
+    app.add_event::<ChatRejectedEvent>();
+    app.add_event::<AnnouncementEvent>();
+    app.add_event::<ServerShuttingDownEvent>();
+
+    app.insert_resource(PlayerName::from_args_or_env());
+    app.insert_resource(ServerAddress::from_args_env_or_settings());
+    app.insert_resource(CertificateTrust::from_args_or_env());
+    app.insert_resource(InterpolationSettings::from_args_or_env());
+    app.insert_resource(SpectatorMode::from_args_or_env());
+    app.init_resource::<Roster>();
+    app.init_resource::<ConnectScreenState>();
+    app.init_resource::<SessionToken>();
+    app.init_resource::<ReconnectState>();
+    app.init_resource::<ConnectionStatus>();
+    app.init_resource::<Latency>();
+    app.init_resource::<PingTimer>();
+    app.init_resource::<ServerClock>();
+    app.init_resource::<SnapshotHistory>();
+    app.init_resource::<RoomScreenState>();
+    app.init_resource::<RaceState>();
+    app.init_resource::<ChatState>();
+    app.init_resource::<SpectatorCamera>();
 
     app.insert_resource(player_movement::UpdatePlayerMovementTimer(
         Timer::from_seconds(0.02, TimerMode::Repeating),
     ));
 
-    app.add_systems(Startup, start_connection);
+    app.add_systems(
+        Startup,
+        (
+            start_connection,
+            connect_screen::setup,
+            room_screen::setup,
+            chat::setup,
+            spectator::setup,
+        ),
+    );
     app.add_systems(
         Update,
         (
@@ -53,59 +145,194 @@ pub fn setup_client(app: &mut App) {
             player_movement::update_player_movement.run_if(is_player_connected),
             ghost_player::moved_players_updated,
             highscore::on_player_finish_level.run_if(is_player_connected),
+            highscore::on_run_started
+                .after(highscore::on_player_finish_level)
+                .run_if(is_player_connected),
+            leaderboard::on_leaderboard_opened.run_if(is_player_connected),
+            roster::on_roster_updated,
+            roster::spawn_name_tags,
+            roster::update_name_tags,
+            connect_screen::toggle_connect_screen.run_if(chat::is_chat_closed),
+            connect_screen::type_server_address,
+            connect_screen::update_connect_screen,
+            handle_connect_request,
+            certificate::on_certificate_trusted,
+            certificate::on_certificate_interaction,
+            certificate::on_certificate_rejected,
+            handshake::on_handshake_accepted.run_if(is_player_connected),
+            handshake::on_handshake_rejected,
+        ),
+    );
+    app.add_systems(Update, handshake::on_join_rejected);
+    app.add_systems(
+        Update,
+        (
+            reconnect::on_join_accepted,
+            reconnect::schedule_reconnect.after(certificate::on_certificate_rejected),
+            reconnect::reconnect,
+            latency::send_ping.run_if(is_player_connected),
+            latency::on_pong_received,
+            latency::reset_latency,
+            interpolation::reset_server_clock.before(ghost_player::moved_players_updated),
+            ghost_player::reset_snapshot_history.before(ghost_player::moved_players_updated),
+            interpolation::interpolate_ghosts.after(ghost_player::moved_players_updated),
+            room_screen::toggle_room_screen.run_if(chat::is_chat_closed),
+            room_screen::type_room_command,
+            room_screen::on_room_messages,
+            room_screen::update_room_screen,
+            race::toggle_ready
+                .run_if(spectator::is_playing)
+                .run_if(is_player_connected)
+                .run_if(connect_screen::is_connect_screen_closed)
+                .run_if(room_screen::is_room_screen_closed)
+                .run_if(chat::is_chat_closed),
+            race::on_race_messages,
+            race::start_race.after(race::on_race_messages),
+            chat::toggle_chat
+                .run_if(spectator::is_playing)
+                .run_if(is_player_connected)
+                .run_if(connect_screen::is_connect_screen_closed)
+                .run_if(room_screen::is_room_screen_closed),
+            chat::type_chat_message.before(chat::toggle_chat),
+            chat::on_chat_messages,
+            chat::update_chat_overlay,
+        ),
+    );
+    app.add_systems(
+        Update,
+        (
+            (
+                spectator::cycle_followed_player
+                    .run_if(connect_screen::is_connect_screen_closed)
+                    .run_if(room_screen::is_room_screen_closed),
+                spectator::move_spectator_camera
+                    .after(spectator::cycle_followed_player)
+                    .after(interpolation::interpolate_ghosts)
+                    .run_if(connect_screen::is_connect_screen_closed)
+                    .run_if(room_screen::is_room_screen_closed),
+                spectator::update_spectator_label,
+            )
+                .run_if(spectator::is_spectating),
+            handshake::on_kicked,
         ),
     );
 }
 
-/// Opens the connection to the server with the [SERVER_IP_ADDR] and [SERVER_PORT] using the `bevy_quinnet` library.
-fn start_connection(mut client: ResMut<Client>) {
-    let server_addr_str = format!("{}:{}", SERVER_IP_ADDR, SERVER_PORT);
+/// Opens the connection to the server at the [`ServerAddress`] using the `bevy_quinnet` library.
+/// The certificate of the server is verified as configured in the [`CertificateTrust`].
+fn start_connection(
+    mut client: ResMut<Client>,
+    server_address: Res<ServerAddress>,
+    trust: Res<CertificateTrust>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    open_connection(&mut client, &server_address, &trust, &mut status);
+}
+
+/// Called when the player entered a new server address on the connect screen. Closes the current connection,
+/// removes all ghost players of the old server and connects to the new one. The session and room of the old
+/// server are dropped and pending reconnect attempts are stopped.
+fn handle_connect_request(
+    mut events: EventReader<ConnectRequestedEvent>,
+    mut client: ResMut<Client>,
+    mut server_address: ResMut<ServerAddress>,
+    trust: Res<CertificateTrust>,
+    mut session_token: ResMut<SessionToken>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    mut status: ResMut<ConnectionStatus>,
+    mut roster: ResMut<Roster>,
+    mut room_screen: ResMut<RoomScreenState>,
+    mut query: Query<Entity, With<crate::asset_system::players::GhostPlayer>>,
+    mut commands: Commands,
+) {
+    // Everything known about the old server has to be reset.
+    #![allow(clippy::too_many_arguments)]
+    let Some(ev) = events.read().last() else {
+        return;
+    };
+
+    if let Some(connection) = client.get_connection() {
+        connection.try_send_message(PlayerMessage::LeaveGame);
+    }
+    if let Err(error) = client.close_all_connections() {
+        error!(%error, "Error closing connection to server.");
+    }
+    for entity in query.iter_mut() {
+        ghost_player::despawn_player(&mut commands, entity);
+    }
+    roster.clear();
+    room_screen.current = None;
+    room_screen.rooms.clear();
+    session_token.0 = None;
+    reconnect_state.reset();
+
+    *server_address = ev.0.clone();
+    open_connection(&mut client, &server_address, &trust, &mut status);
+}
+
+/// Opens a connection to the server at the given address and updates the [`ConnectionStatus`].
+/// Failing to connect is reported as [`ConnectionLostEvent`].
+pub fn open_connection(
+    client: &mut Client,
+    server_address: &ServerAddress,
+    trust: &CertificateTrust,
+    status: &mut ConnectionStatus,
+) {
+    info!(server = %server_address.input, addr = %server_address.addr, "Connecting to server.");
     let connection_config_result =
-        ConnectionConfiguration::from_strings(&server_addr_str, LOCAL_BIND_ADDR);
+        ConnectionConfiguration::from_strings(&server_address.addr.to_string(), LOCAL_BIND_ADDR);
 
     match connection_config_result {
         Ok(connection_config) => {
             let open_connection_result = client.open_connection(
                 connection_config,
-                CertificateVerificationMode::SkipVerification,
+                trust.verification_mode(),
             );
 
             match open_connection_result {
-                Ok(_) => {}
-                Err(error) => println!("Error opening connection to server: {}", error),
+                Ok(_) => *status = ConnectionStatus::Connecting,
+                Err(error) => {
+                    error!(%error, "Error opening connection to server.");
+                    *status = ConnectionStatus::Offline;
+                }
             }
         }
-        Err(error) => println!("Error creating connection configuration: {}", error),
+        Err(error) => {
+            error!(%error, "Error creating connection configuration.");
+            *status = ConnectionStatus::Offline;
+        }
     }
 }
 
 /// Called when the player connects to the server.
 ///
 /// This event does **not** mean the player already joined the game. It just means that the connection
-/// to the server was successful. To join the game the player has to send a [PlayerMessage::JoinGame] message.
+/// to the server was successful. The game first sends a [PlayerMessage::Handshake] message, so the server can check
+/// if it speaks the same protocol. Once the server accepted it, the player joins the game in
+/// [`handshake::on_handshake_accepted`].
 fn handle_connection_event(
     client: Res<Client>,
     mut connection_event: EventReader<ConnectionEvent>,
+    mut status: ResMut<ConnectionStatus>,
 ) {
     if !connection_event.is_empty() {
         connection_event.clear();
+        *status = ConnectionStatus::Connected;
 
-        let message = PlayerMessage::JoinGame(PlayerMovement {
-            velocity_x: 0.0,
-            velocity_y: 0.0,
-            translation_x: 0.0,
-            translation_y: 0.0,
-        });
-
-        client.connection().try_send_message(message);
+        client
+            .connection()
+            .try_send_message(PlayerMessage::Handshake(handshake::game_handshake()));
     }
 }
 
 fn is_player_connected(client: Res<Client>) -> bool {
-    client.connection().is_connected()
+    // There is no connection if opening the last one failed
+    client
+        .get_connection()
+        .is_some_and(|connection| connection.is_connected())
 }
 
-/// Called when the player loses the connection to the server.
+/// Called when the player loses the connection to the server. Reconnecting is handled by [`reconnect::schedule_reconnect`].
 fn handle_connection_lost_event(
     mut connection_lost_event: EventReader<ConnectionLostEvent>,
     mut query: Query<Entity, With<crate::asset_system::players::GhostPlayer>>,
@@ -119,28 +346,126 @@ fn handle_connection_lost_event(
     }
 }
 
+/// Writers for the events fired by [`handle_server_messages`]. Grouped, as a system can not take more than
+/// 16 arguments.
+#[derive(SystemParam)]
+struct ServerMessageWriters<'w> {
+    handshake_accepted: EventWriter<'w, HandshakeAcceptedEvent>,
+    handshake_rejected: EventWriter<'w, HandshakeRejectedEvent>,
+    ghost_players_moved: EventWriter<'w, GhostPlayersMovedEvent>,
+    highscore_info: EventWriter<'w, HighscoreInfoEvent>,
+    highscore_removed: EventWriter<'w, HighscoreRemovedEvent>,
+    run_time_confirmed: EventWriter<'w, RunTimeConfirmedEvent>,
+    leaderboard_info: EventWriter<'w, LeaderboardInfoEvent>,
+    roster_updated: EventWriter<'w, RosterUpdatedEvent>,
+    join_accepted: EventWriter<'w, JoinAcceptedEvent>,
+    join_rejected: EventWriter<'w, JoinRejectedEvent>,
+    pong_received: EventWriter<'w, PongReceivedEvent>,
+    room_list: EventWriter<'w, RoomListEvent>,
+    room_joined: EventWriter<'w, RoomJoinedEvent>,
+    room_rejected: EventWriter<'w, RoomRejectedEvent>,
+    race_ready: EventWriter<'w, RaceReadyEvent>,
+    race_countdown: EventWriter<'w, RaceCountdownEvent>,
+    race_results: EventWriter<'w, RaceResultsEvent>,
+    chat_received: EventWriter<'w, ChatReceivedEvent>,
+    chat_rejected: EventWriter<'w, ChatRejectedEvent>,
+    announcement: EventWriter<'w, AnnouncementEvent>,
+    kicked: EventWriter<'w, KickedEvent>,
+    server_shutting_down: EventWriter<'w, ServerShuttingDownEvent>,
+}
+
 /// Handles all messages sent from the server to the client. Check [shared::ServerMessage] for all possible messages.
 ///
 /// Messages received are then handled by the responsible system:
+/// * [ServerMessage::HandshakeAccepted] - Handled by [`handshake::on_handshake_accepted`]
+/// * [ServerMessage::HandshakeRejected] - Handled by [`handshake::on_handshake_rejected`]
 /// * [ServerMessage::UpdateMovedPlayers] - Handled by [`ghost_player::moved_players_updated`]
 /// * [ServerMessage::InformAboutHighscore] - Handled by [`highscore::highscore_updated`]
-fn handle_server_messages(
-    mut client: ResMut<Client>,
-
-    mut ev_ghost_players_moved: EventWriter<GhostPlayersMovedEvent>,
-    mut ev_highscore_info: EventWriter<HighscoreInfoEvent>,
-) {
+/// * [ServerMessage::RunTimeConfirmed] - Handled by [`crate::score_system::time::update_last_run`]
+/// * [ServerMessage::Leaderboard] - Handled by [`crate::score_system::leaderboard::update_leaderboard`]
+/// * [ServerMessage::PlayerRoster] - Handled by [`roster::on_roster_updated`]
+/// * [ServerMessage::JoinRejected] - Handled by [`handshake::on_join_rejected`]
+/// * [ServerMessage::JoinAccepted] - Handled by [`reconnect::on_join_accepted`]
+/// * [ServerMessage::Pong] - Handled by [`latency::on_pong_received`]
+/// * [ServerMessage::RoomList], [ServerMessage::RoomJoined] and [ServerMessage::RoomRejected] - Handled by
+///   [`room_screen::on_room_messages`]
+/// * [ServerMessage::RaceReady], [ServerMessage::RaceCountdown] and [ServerMessage::RaceResults] - Handled by
+///   [`race::on_race_messages`]
+/// * [ServerMessage::ChatBroadcast], [ServerMessage::ChatRejected], [ServerMessage::Announcement] and
+///   [ServerMessage::ServerShuttingDown] - Handled by [`chat::on_chat_messages`]
+/// * [ServerMessage::HighscoreRemoved] - Handled by [`crate::score_system::highscore_label::update_highscore`]
+/// * [ServerMessage::Kicked] - Handled by [`handshake::on_kicked`]
+fn handle_server_messages(mut client: ResMut<Client>, mut writers: ServerMessageWriters) {
     while let Some(message) = client
         .connection_mut()
         .try_receive_message::<ServerMessage>()
     {
         match message {
-            ServerMessage::Pong => println!("Received pong 🏓"),
-            ServerMessage::UpdateMovedPlayers(players_moved_updates) => {
-                ev_ghost_players_moved.send(GhostPlayersMovedEvent(players_moved_updates));
+            ServerMessage::HandshakeAccepted(server_handshake) => {
+                writers.handshake_accepted.send(HandshakeAcceptedEvent(server_handshake));
+            }
+            ServerMessage::HandshakeRejected(reason) => {
+                writers.handshake_rejected.send(HandshakeRejectedEvent(reason));
+            }
+            ServerMessage::Pong => {
+                writers.pong_received.send(PongReceivedEvent);
+            }
+            ServerMessage::UpdateMovedPlayers(snapshot) => {
+                writers.ghost_players_moved.send(GhostPlayersMovedEvent(snapshot));
             }
             ServerMessage::InformAboutHighscore(new_highscore) => {
-                ev_highscore_info.send(HighscoreInfoEvent(new_highscore));
+                writers.highscore_info.send(HighscoreInfoEvent(new_highscore));
+            }
+            ServerMessage::RunTimeConfirmed(official_time) => {
+                writers.run_time_confirmed.send(RunTimeConfirmedEvent(official_time));
+            }
+            ServerMessage::Leaderboard(leaderboard) => {
+                writers.leaderboard_info.send(LeaderboardInfoEvent(leaderboard));
+            }
+            ServerMessage::JoinAccepted(session_info) => {
+                writers.join_accepted.send(JoinAcceptedEvent(session_info));
+            }
+            ServerMessage::JoinRejected(reason) => {
+                writers.join_rejected.send(JoinRejectedEvent(reason));
+            }
+            ServerMessage::PlayerRoster(roster) => {
+                writers.roster_updated.send(RosterUpdatedEvent(roster));
+            }
+            ServerMessage::RoomList(rooms) => {
+                writers.room_list.send(RoomListEvent(rooms));
+            }
+            ServerMessage::RoomJoined(room) => {
+                writers.room_joined.send(RoomJoinedEvent(room));
+            }
+            ServerMessage::RoomRejected(reason) => {
+                writers.room_rejected.send(RoomRejectedEvent(reason));
+            }
+            ServerMessage::RaceReady(ready_players) => {
+                writers.race_ready.send(RaceReadyEvent(ready_players));
+            }
+            ServerMessage::RaceCountdown(start_at) => {
+                writers.race_countdown.send(RaceCountdownEvent(start_at));
+            }
+            ServerMessage::RaceResults(placings) => {
+                writers.race_results.send(RaceResultsEvent(placings));
+            }
+            ServerMessage::ChatBroadcast(message) => {
+                writers.chat_received.send(ChatReceivedEvent(message));
+            }
+            ServerMessage::ChatRejected(reason) => {
+                writers.chat_rejected.send(ChatRejectedEvent(reason));
+            }
+            ServerMessage::Announcement(text) => {
+                writers.announcement.send(AnnouncementEvent(text));
+            }
+            ServerMessage::HighscoreRemoved(level_iid) => {
+                writers.highscore_removed.send(HighscoreRemovedEvent(level_iid));
+            }
+            ServerMessage::Kicked(reason) => {
+                writers.kicked.send(KickedEvent(reason));
+            }
+            ServerMessage::ServerShuttingDown(info) => {
+                writers.server_shutting_down.send(ServerShuttingDownEvent(info));
             }
         }
     }
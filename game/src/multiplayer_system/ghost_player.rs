@@ -1,38 +1,109 @@
 use crate::asset_system::ghost_physics::GhostColliderBundle;
 use crate::asset_system::players::{GhostPlayer, GhostPlayerBundle};
+use crate::multiplayer_system::interpolation::{ServerClock, Snapshot, SnapshotBuffer};
 use bevy::asset::AssetServer;
 use bevy::ecs::event::{Event, EventReader};
-use bevy::math::{Vec2, Vec3};
+use bevy::log::debug;
+use bevy::math::Vec2;
 use bevy::prelude::{
-    Commands, Entity, GlobalTransform, Mut, Query, Res, SpriteSheetBundle, TextureAtlas,
-    TextureAtlasSprite, Transform, With,
+    Commands, DespawnRecursiveExt, Entity, Mut, Query, Res, ResMut, Resource, SpriteSheetBundle,
+    TextureAtlas, TextureAtlasSprite, Time, Transform, With,
 };
+use bevy::time::Real;
 use bevy::utils::hashbrown::HashMap;
+use bevy_quinnet::client::connection::ConnectionEvent;
 use bevy_rapier2d::dynamics::{LockedAxes, RigidBody, Velocity};
 use bevy_rapier2d::geometry::{Collider, Friction};
-use shared::PlayerMovedUpdate;
+use shared::{MovedPlayersSnapshot, PlayerMovedUpdate, PlayerStates};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Maximum number of restored snapshots kept as baselines.
+const MAX_SNAPSHOT_HISTORY: usize = 64;
 
 /// Bevy event to be fired when server sends info about positions of ghost players.
 #[derive(Event)]
-pub struct GhostPlayersMovedEvent(pub Vec<PlayerMovedUpdate>);
+pub struct GhostPlayersMovedEvent(pub MovedPlayersSnapshot);
+
+/// Movements of all ghost players restored from the latest snapshots, newest last.
+///
+/// The server only sends the changes since the latest snapshot the game acknowledged, so they are kept
+/// as baselines to restore the following snapshots.
+#[derive(Resource, Default)]
+pub struct SnapshotHistory(VecDeque<(u32, PlayerStates)>);
+
+impl SnapshotHistory {
+    /// Tick of the newest restored snapshot, sent to the server as acknowledgement.
+    pub fn latest_tick(&self) -> Option<u32> {
+        self.0.back().map(|(tick, _)| *tick)
+    }
+
+    /// Restores the movements of all players from the snapshot and keeps them as baseline.
+    /// Returns `None` if the baseline of the snapshot is no longer known, the next keyframe fixes that.
+    fn restore(&mut self, snapshot: &MovedPlayersSnapshot) -> Option<PlayerStates> {
+        let baseline = snapshot.baseline_tick.and_then(|baseline_tick| {
+            self.0
+                .iter()
+                .find(|(tick, _)| *tick == baseline_tick)
+                .map(|(_, states)| states)
+        });
+        let states = snapshot.apply(baseline)?;
+
+        self.0.push_back((snapshot.tick, states.clone()));
+        while self.0.len() > MAX_SNAPSHOT_HISTORY {
+            self.0.pop_front();
+        }
+        Some(states)
+    }
+}
+
+/// Forgets the snapshots of the previous connection whenever a new connection was established.
+pub fn reset_snapshot_history(
+    mut connection_event: EventReader<ConnectionEvent>,
+    mut history: ResMut<SnapshotHistory>,
+) {
+    if !connection_event.is_empty() {
+        connection_event.clear();
+        *history = SnapshotHistory::default();
+    }
+}
 
+/// Stores the positions sent by the server as new snapshots of the ghost players.
+///
+/// Ghost players are not moved here, [`crate::multiplayer_system::interpolation::interpolate_ghosts`]
+/// moves them smoothly between the snapshots. Ghost players missing in the update are despawned and
+/// new ones are spawned.
+///
+/// Snapshots with a tick older than the latest one are dropped, the others are restored with the
+/// [`SnapshotHistory`]. If multiple snapshots arrived since the last frame only the newest one is shown.
 pub fn moved_players_updated(
+    time: Res<Time<Real>>,
+    mut server_clock: ResMut<ServerClock>,
+    mut history: ResMut<SnapshotHistory>,
     mut events: EventReader<GhostPlayersMovedEvent>,
-    mut query: Query<
-        (
-            &mut Velocity,
-            &mut GlobalTransform,
-            &mut Transform,
-            &GhostPlayer,
-            Entity,
-        ),
-        With<GhostPlayer>,
-    >,
+    mut query: Query<(&mut Velocity, &mut SnapshotBuffer, &GhostPlayer, Entity), With<GhostPlayer>>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
 ) {
+    let mut latest = None;
     for ev in events.read() {
-        let players_moved_updates = ev.0.to_owned();
+        if !server_clock.ticks.accept(ev.0.tick) {
+            continue;
+        }
+        if let Some(states) = history.restore(&ev.0) {
+            latest = Some((ev.0.server_time, states));
+        }
+    }
+
+    if let Some((server_time, states)) = latest {
+        server_clock.observe(server_time, time.elapsed());
+        let players_moved_updates = states
+            .into_iter()
+            .map(|(id, movement)| PlayerMovedUpdate {
+                id,
+                movement: movement.into(),
+            })
+            .collect();
 
         let mut player_id_list: Vec<u64> = Vec::new();
         let mut player_velocities_server: HashMap<u64, Vec2> = HashMap::new();
@@ -43,28 +114,31 @@ pub fn moved_players_updated(
             &mut player_velocities_server,
             &mut player_transforms_server,
         );
-        for (mut ghost_velocity, _ghost_transform, mut transform, mut ghost_player, entity) in
-            &mut query
-        {
+        for (mut ghost_velocity, mut snapshots, mut ghost_player, entity) in &mut query {
             if player_id_list.contains(&ghost_player.id) {
                 move_player(
                     &mut player_id_list,
                     &mut player_velocities_server,
                     &mut player_transforms_server,
                     &mut ghost_velocity,
-                    &mut transform,
+                    &mut snapshots,
                     &mut ghost_player,
+                    server_time,
                 );
             } else {
-                println!("Despawning player with id: {}", ghost_player.id);
+                debug!(player_id = ghost_player.id, "Despawning ghost player.");
                 //remove the player
                 despawn_player(&mut commands, entity);
             }
         }
         for id in player_id_list {
-            //spawn the player
-
-            spawn_player(&mut commands, &asset_server, id);
+            //spawn the player at its current position
+            let snapshot = Snapshot {
+                server_time,
+                position: player_transforms_server[&id],
+                velocity: player_velocities_server[&id],
+            };
+            spawn_player(&mut commands, &asset_server, id, snapshot);
         }
     }
 }
@@ -93,14 +167,19 @@ fn move_player(
     player_velocities_server: &mut HashMap<u64, Vec2>,
     player_transforms_server: &mut HashMap<u64, Vec2>,
     ghost_velocity: &mut Mut<Velocity>,
-    transform: &mut Mut<Transform>,
+    snapshots: &mut Mut<SnapshotBuffer>,
     ghost_player: &mut &GhostPlayer,
+    server_time: Duration,
 ) {
     let server_velocity = player_velocities_server.get(&ghost_player.id).unwrap();
     let server_transform = player_transforms_server.get(&ghost_player.id).unwrap();
     ghost_velocity.linvel.x = server_velocity.x;
     ghost_velocity.linvel.y = server_velocity.y;
-    transform.translation = Vec3::new(server_transform.x, server_transform.y, 0.0);
+    snapshots.push(Snapshot {
+        server_time,
+        position: *server_transform,
+        velocity: *server_velocity,
+    });
 
     //remove the player from the list
     player_id_list.retain(|&x| x != ghost_player.id);
@@ -108,7 +187,7 @@ fn move_player(
 /// Despawns a ghost player entity
 ///
 /// This function is responsible for despawning a ghost player entity from the ECS.
-/// It removes the `GhostPlayer` component from the entity and then despawns the entity itself.
+/// It removes the `GhostPlayer` component from the entity and then despawns the entity itself together with its name tag.
 ///
 /// # Arguments
 ///
@@ -116,26 +195,32 @@ fn move_player(
 /// * `entity` - The `Entity` struct representing the ghost player to be despawned.
 ///
 pub fn despawn_player(commands: &mut Commands, entity: Entity) {
-    commands.entity(entity).despawn();
+    commands.entity(entity).despawn_recursive();
     commands.entity(entity).remove::<GhostPlayer>();
 }
 /// Spawns a new ghost player entity
 ///
 /// This function is responsible for spawning a new ghost player entity in the ECS.
 /// It loads the sprite for the player based on their id, creates a texture atlas from the sprite,
-/// and then spawns a new entity with the `GhostPlayerBundle`.
+/// and then spawns a new entity with the `GhostPlayerBundle` and a `SnapshotBuffer` at the position
+/// of the first snapshot. Ghost players are kinematic bodies, they are moved by their snapshots instead of physics.
 ///
 /// # Arguments
 ///
 /// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
 /// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
 /// * `id` - The id of the player to spawn.
+/// * `snapshot` - The first snapshot of the player sent by the server.
 ///
-fn spawn_player(commands: &mut Commands, asset_server: &Res<AssetServer>, id: u64) {
-    println!("Spawning player with id: {}", id);
+fn spawn_player(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    id: u64,
+    snapshot: Snapshot,
+) {
+    debug!(player_id = id, "Spawning ghost player.");
 
     let texture_handle = asset_server.load(get_sprite_filename(id));
-    println!("loading test.png");
     let texture_atlas = TextureAtlas::from_grid(
         texture_handle.clone(),
         Vec2::new(16.0, 16.0),
@@ -146,25 +231,28 @@ fn spawn_player(commands: &mut Commands, asset_server: &Res<AssetServer>, id: u6
     );
 
     let texture_atlas_handle = asset_server.add(texture_atlas);
-    commands.spawn(GhostPlayerBundle {
-        ghost_player: GhostPlayer { id },
-        sprite_sheet_bundle: SpriteSheetBundle {
-            texture_atlas: texture_atlas_handle,
-            sprite: TextureAtlasSprite::new(0),
-            transform: Transform {
-                translation: Vec3::new(300.0, 300.0, 99999999.0),
+    commands.spawn((
+        GhostPlayerBundle {
+            ghost_player: GhostPlayer { id },
+            sprite_sheet_bundle: SpriteSheetBundle {
+                texture_atlas: texture_atlas_handle,
+                sprite: TextureAtlasSprite::new(0),
+                transform: Transform {
+                    translation: snapshot.position.extend(99999999.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ghost_collider_bundle: GhostColliderBundle {
+                collider: Collider::cuboid(6.5, 8.0),
+                rigid_body: RigidBody::KinematicPositionBased,
+                friction: Friction::new(0.0),
+                rotation_constraints: LockedAxes::ROTATION_LOCKED,
                 ..Default::default()
             },
-            ..Default::default()
-        },
-        ghost_collider_bundle: GhostColliderBundle {
-            collider: Collider::cuboid(6.5, 8.0),
-            rigid_body: RigidBody::Dynamic,
-            friction: Friction::new(0.0),
-            rotation_constraints: LockedAxes::ROTATION_LOCKED,
-            ..Default::default()
         },
-    });
+        SnapshotBuffer::new(snapshot),
+    ));
 }
 /// Returns the filename of the sprite for a given player id
 ///
@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use bevy_quinnet::client::Client;
+use shared::{
+    Handshake, JoinRequest, PlayerMessage, PlayerMovement, SpectateRequest, PROTOCOL_VERSION,
+};
+
+use crate::multiplayer_system::connect_screen::ConnectScreenState;
+use crate::multiplayer_system::connection::ConnectionStatus;
+use crate::multiplayer_system::reconnect::{ReconnectState, SessionToken};
+use crate::multiplayer_system::roster::PlayerName;
+use crate::multiplayer_system::server_address::ServerAddress;
+use crate::multiplayer_system::spectator::SpectatorMode;
+
+/// Bevy event to be fired when the server accepted the handshake of the game. Contains the handshake of the server.
+#[derive(Event)]
+pub struct HandshakeAcceptedEvent(pub Handshake);
+
+/// Bevy event to be fired when the server refused the game because it is incompatible. Contains the reason.
+#[derive(Event)]
+pub struct HandshakeRejectedEvent(pub String);
+
+/// Bevy event to be fired when the server refused the player to join the game, e.g. because of an invalid name.
+/// Contains the reason.
+#[derive(Event)]
+pub struct JoinRejectedEvent(pub String);
+
+/// Bevy event to be fired when the server is about to close the connection, e.g. because the player was banned.
+/// Contains the reason.
+#[derive(Event)]
+pub struct KickedEvent(pub String);
+
+/// The handshake sent to the server right after connecting.
+pub fn game_handshake() -> Handshake {
+    Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        build: format!("game {}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+/// Called when the server accepted the handshake. Joins the game by sending a [PlayerMessage::JoinGame] message.
+/// When reconnecting the message contains the [`SessionToken`] of the previous session.
+/// Spectators send a [PlayerMessage::Spectate] message instead.
+pub fn on_handshake_accepted(
+    mut events: EventReader<HandshakeAcceptedEvent>,
+    client: Res<Client>,
+    player_name: Res<PlayerName>,
+    session_token: Res<SessionToken>,
+    spectator_mode: Res<SpectatorMode>,
+) {
+    for ev in events.read() {
+        info!(build = %ev.0.build, "Connected to server.");
+        if spectator_mode.0 {
+            client
+                .connection()
+                .try_send_message(PlayerMessage::Spectate(SpectateRequest {
+                    name: player_name.0.clone(),
+                    session_token: session_token.0.clone(),
+                }));
+            continue;
+        }
+
+        let message = PlayerMessage::JoinGame(JoinRequest {
+            name: player_name.0.clone(),
+            movement: PlayerMovement {
+                velocity_x: 0.0,
+                velocity_y: 0.0,
+                translation_x: 0.0,
+                translation_y: 0.0,
+            },
+            session_token: session_token.0.clone(),
+        });
+
+        client.connection().try_send_message(message);
+    }
+}
+
+/// Called when the server refused the game. Closes the connection without reconnecting and shows the reason
+/// on the connect screen, where the player can choose another server.
+pub fn on_handshake_rejected(
+    mut events: EventReader<HandshakeRejectedEvent>,
+    mut client: ResMut<Client>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    mut status: ResMut<ConnectionStatus>,
+    server_address: Res<ServerAddress>,
+    mut connect_screen: ResMut<ConnectScreenState>,
+) {
+    for ev in events.read() {
+        warn!(reason = %ev.0, "Server refused the connection.");
+        go_offline(
+            &mut client,
+            &mut reconnect_state,
+            &mut status,
+            &server_address,
+            &mut connect_screen,
+            &ev.0,
+        );
+    }
+}
+
+/// Called when the server kicked the player. Like [`on_handshake_rejected`] the game does not reconnect and
+/// shows the reason on the connect screen. The session is gone on the server, so it is forgotten as well.
+pub fn on_kicked(
+    mut events: EventReader<KickedEvent>,
+    mut client: ResMut<Client>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    mut session_token: ResMut<SessionToken>,
+    mut status: ResMut<ConnectionStatus>,
+    server_address: Res<ServerAddress>,
+    mut connect_screen: ResMut<ConnectScreenState>,
+) {
+    for ev in events.read() {
+        warn!(reason = %ev.0, "Kicked by the server.");
+        session_token.0 = None;
+        go_offline(
+            &mut client,
+            &mut reconnect_state,
+            &mut status,
+            &server_address,
+            &mut connect_screen,
+            &ev.0,
+        );
+    }
+}
+
+/// Called when the server refused the player to join the game. Like [`on_handshake_rejected`] the game does not
+/// reconnect, as the server would refuse again, and shows the reason on the connect screen.
+pub fn on_join_rejected(
+    mut events: EventReader<JoinRejectedEvent>,
+    mut client: ResMut<Client>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    mut status: ResMut<ConnectionStatus>,
+    server_address: Res<ServerAddress>,
+    mut connect_screen: ResMut<ConnectScreenState>,
+) {
+    for ev in events.read() {
+        warn!(reason = %ev.0, "Server rejected joining the game.");
+        go_offline(
+            &mut client,
+            &mut reconnect_state,
+            &mut status,
+            &server_address,
+            &mut connect_screen,
+            &ev.0,
+        );
+    }
+}
+
+/// Closes the connection without reconnecting and opens the connect screen with the given reason.
+fn go_offline(
+    client: &mut Client,
+    reconnect_state: &mut ReconnectState,
+    status: &mut ConnectionStatus,
+    server_address: &ServerAddress,
+    connect_screen: &mut ConnectScreenState,
+    reason: &str,
+) {
+    if let Err(error) = client.close_all_connections() {
+        error!(%error, "Error closing connection to server.");
+    }
+    reconnect_state.reset();
+    *status = ConnectionStatus::Offline;
+
+    connect_screen.open = true;
+    connect_screen.input = server_address.input.clone();
+    connect_screen.error = Some(reason.to_string());
+}
@@ -2,24 +2,58 @@ use bevy::ecs::{
     event::{Event, EventReader},
     system::Res,
 };
+use bevy::log::warn;
 use bevy_quinnet::client::Client;
 use shared::{Highscore, PlayerMessage};
 
 use crate::asset_system::finish_lines::FinishLineEvent;
+use crate::asset_system::levels::CurrentLevel;
+use crate::score_system::time::RunStartedEvent;
 
 /// Bevy event to be fired when server sends info about a new highscore.
 #[derive(Event)]
 pub struct HighscoreInfoEvent(pub Highscore);
 
-/// Called when the player finishes the level. Sends a request to the server if the player has set a new highscore.
-/// If yes the server sends a [`ServerMessage::InformAboutHighscore`] message.
-pub fn on_player_finish_level(mut events: EventReader<FinishLineEvent>, client: Res<Client>) {
+/// Bevy event to be fired when the server operator removed the highscore of a level. Contains the level iid.
+#[derive(Event)]
+pub struct HighscoreRemovedEvent(pub String);
+
+/// Bevy event to be fired when server sends the official time of the last finished run.
+#[derive(Event)]
+pub struct RunTimeConfirmedEvent(pub Highscore);
+
+/// Called when the player finishes the level. Sends a [`PlayerMessage::RunFinished`] message to the server, which
+/// measures the official time of the run and answers with a [`ServerMessage::RunTimeConfirmed`] message.
+/// If the run is a new highscore the server also sends a [`ServerMessage::InformAboutHighscore`] message.
+pub fn on_player_finish_level(
+    mut events: EventReader<FinishLineEvent>,
+    client: Res<Client>,
+    current_level: Res<CurrentLevel>,
+) {
     for ev in events.read() {
+        let Some(level_iid) = current_level.iid.clone() else {
+            warn!("Finished a level before it was spawned, run not reported.");
+            continue;
+        };
         let highscore = Highscore {
-            time_in_seconds: ev.elapsed_time,
+            level_iid,
+            time: ev.elapsed_time,
         };
         client
             .connection()
-            .try_send_message(PlayerMessage::RequestPossibleHighscore(highscore));
+            .try_send_message(PlayerMessage::RunFinished(highscore));
+    }
+}
+
+/// Called when the player starts a new run, e.g. after hitting a trap or finishing the level.
+/// Sends a [`PlayerMessage::RunStarted`] message so the server starts measuring the run.
+///
+/// Has to run after [`on_player_finish_level`], otherwise the server would start the new run before
+/// the finished one is reported.
+pub fn on_run_started(mut events: EventReader<RunStartedEvent>, client: Res<Client>) {
+    for _ in events.read() {
+        client
+            .connection()
+            .try_send_message(PlayerMessage::RunStarted);
     }
 }
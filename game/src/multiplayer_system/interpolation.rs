@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::env;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::Real;
+use bevy_quinnet::client::connection::ConnectionEvent;
+use shared::SequenceTracker;
+
+use crate::asset_system::players::GhostPlayer;
+
+/// Environment variable to set the render delay of ghost players in milliseconds.
+const RENDER_DELAY_ENV: &str = "GHOST_RENDER_DELAY_MS";
+
+/// Command line argument to set the render delay of ghost players in milliseconds, e.g. `--render-delay-ms 150`.
+const RENDER_DELAY_ARG: &str = "--render-delay-ms";
+
+/// Render delay used when none is set. Covers five updates of the server, so one or two late updates
+/// do not make the ghosts stop.
+const DEFAULT_RENDER_DELAY: Duration = Duration::from_millis(100);
+
+/// How far ghost players are moved ahead with their last velocity when no newer update arrived.
+/// They stop afterwards, so a player who left does not fly off the map.
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(250);
+
+/// Maximum number of snapshots kept per ghost player.
+const MAX_SNAPSHOTS: usize = 32;
+
+/// How ghost players are moved between the updates sent by the server.
+///
+/// # Fields
+///
+/// * `render_delay` - Ghost players are shown where they were this long ago, so there is usually a newer
+///   snapshot to interpolate towards. Higher values are smoother but lag further behind.
+#[derive(Resource)]
+pub struct InterpolationSettings {
+    pub render_delay: Duration,
+}
+
+impl InterpolationSettings {
+    /// Reads the render delay from the `--render-delay-ms` command line argument or the `GHOST_RENDER_DELAY_MS`
+    /// environment variable. Falls back to 100 ms if neither is set or valid.
+    pub fn from_args_or_env() -> Self {
+        let mut args = env::args().skip_while(|arg| arg != RENDER_DELAY_ARG).skip(1);
+        let input = args.next().or_else(|| env::var(RENDER_DELAY_ENV).ok());
+
+        let render_delay = match input.map(|input| input.trim().parse::<u64>()) {
+            Some(Ok(millis)) => Duration::from_millis(millis),
+            Some(Err(_)) => {
+                warn!(
+                    "Invalid render delay, using {} ms instead.",
+                    DEFAULT_RENDER_DELAY.as_millis()
+                );
+                DEFAULT_RENDER_DELAY
+            }
+            None => DEFAULT_RENDER_DELAY,
+        };
+        InterpolationSettings { render_delay }
+    }
+}
+
+/// Weight of a new sample when the estimated offset to the server time decreases. Samples increasing the offset
+/// are taken immediately, as they arrived faster than all before.
+const CLOCK_SMOOTHING_FACTOR: f64 = 0.01;
+
+/// Time and tick of the server as seen by the game.
+///
+/// # Fields
+///
+/// * `offset` - Estimated difference between the time of the server and the real time of the game in seconds.
+///   `None` until the first snapshot arrived.
+/// * `ticks` - Tracks the ticks of the received snapshots to drop stale ones and measure the packet loss.
+#[derive(Resource, Default)]
+pub struct ServerClock {
+    pub offset: Option<f64>,
+    pub ticks: SequenceTracker,
+}
+
+impl ServerClock {
+    /// Updates the estimated offset with a snapshot taken at `server_time` which arrived at `local_time`.
+    ///
+    /// The fastest snapshots arrive closest to the time they were taken, so the offset follows them directly
+    /// and only slowly drifts back when snapshots are delayed.
+    pub fn observe(&mut self, server_time: Duration, local_time: Duration) {
+        let sample = server_time.as_secs_f64() - local_time.as_secs_f64();
+        self.offset = Some(match self.offset {
+            Some(offset) if sample < offset => offset + (sample - offset) * CLOCK_SMOOTHING_FACTOR,
+            _ => sample,
+        });
+    }
+
+    /// Estimated time of the server at the given real time of the game.
+    pub fn server_time(&self, local_time: Duration) -> Option<Duration> {
+        let offset = self.offset?;
+        Some(Duration::from_secs_f64(
+            (local_time.as_secs_f64() + offset).max(0.0),
+        ))
+    }
+}
+
+/// Forgets the time and tick of the previous server whenever a new connection was established.
+pub fn reset_server_clock(
+    mut connection_event: EventReader<ConnectionEvent>,
+    mut server_clock: ResMut<ServerClock>,
+) {
+    if !connection_event.is_empty() {
+        connection_event.clear();
+        *server_clock = ServerClock::default();
+    }
+}
+
+/// Position and velocity of a ghost player as sent by the server.
+///
+/// # Fields
+///
+/// * `server_time` - When the server took the snapshot, measured as time since the server started.
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    pub server_time: Duration,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+/// The latest snapshots of a ghost player, oldest first.
+#[derive(Component, Default)]
+pub struct SnapshotBuffer(pub VecDeque<Snapshot>);
+
+impl SnapshotBuffer {
+    pub fn new(snapshot: Snapshot) -> Self {
+        SnapshotBuffer(VecDeque::from([snapshot]))
+    }
+
+    /// Adds a snapshot and drops the oldest ones if the buffer is full. A snapshot which is not newer
+    /// than the newest one replaces it.
+    ///
+    /// Distant players are only updated now and then by the server, in between it repeats their last movement.
+    /// A moving player can not be at the same position twice, so such repeated snapshots are ignored and the
+    /// player is extrapolated instead.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if let Some(last) = self.0.back_mut() {
+            if last.server_time >= snapshot.server_time {
+                *last = snapshot;
+                return;
+            }
+            let repeated = last.position == snapshot.position && last.velocity == snapshot.velocity;
+            if repeated && snapshot.velocity != Vec2::ZERO {
+                return;
+            }
+        }
+        self.0.push_back(snapshot);
+        while self.0.len() > MAX_SNAPSHOTS {
+            self.0.pop_front();
+        }
+    }
+
+    /// Position of the ghost player at the given time.
+    ///
+    /// Between two snapshots the position is interpolated linearly. After the newest snapshot it is
+    /// extrapolated with the velocity of that snapshot for at most [`MAX_EXTRAPOLATION`].
+    pub fn position_at(&self, render_time: Duration) -> Option<Vec2> {
+        let first = self.0.front()?;
+        if render_time <= first.server_time {
+            return Some(first.position);
+        }
+
+        let next_index = self.0.iter().position(|s| s.server_time > render_time);
+        match next_index {
+            Some(index) => {
+                let from = self.0[index - 1];
+                let to = self.0[index];
+                let span = (to.server_time - from.server_time).as_secs_f32();
+                let progress = (render_time - from.server_time).as_secs_f32() / span;
+                Some(from.position.lerp(to.position, progress))
+            }
+            None => {
+                let last = self.0.back()?;
+                let ahead = (render_time - last.server_time).min(MAX_EXTRAPOLATION);
+                Some(last.position + last.velocity * ahead.as_secs_f32())
+            }
+        }
+    }
+
+    /// Removes all snapshots which are no longer needed, keeping the last one before the render time.
+    fn discard_before(&mut self, render_time: Duration) {
+        while self.0.len() > 1 && self.0[1].server_time <= render_time {
+            self.0.pop_front();
+        }
+    }
+}
+
+/// Moves every ghost player to its position at the estimated time of the server minus the render delay.
+///
+/// Ghost players use kinematic bodies, so setting their `Transform` also moves their collider.
+pub fn interpolate_ghosts(
+    time: Res<Time<Real>>,
+    settings: Res<InterpolationSettings>,
+    server_clock: Res<ServerClock>,
+    mut query: Query<(&mut SnapshotBuffer, &mut Transform), With<GhostPlayer>>,
+) {
+    let Some(server_time) = server_clock.server_time(time.elapsed()) else {
+        return;
+    };
+    let render_time = server_time.saturating_sub(settings.render_delay);
+
+    for (mut snapshots, mut transform) in query.iter_mut() {
+        if let Some(position) = snapshots.position_at(render_time) {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+        snapshots.discard_before(render_time);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::Real;
+use bevy_quinnet::client::{connection::ConnectionEvent, Client};
+use shared::PlayerMessage;
+
+/// Interval between two pings sent to the server.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of unanswered pings. No new pings are sent while the server does not answer.
+const MAX_PENDING_PINGS: usize = 5;
+
+/// Weight of a new sample in the smoothed round trip time and jitter, like TCP and RTP do.
+const SMOOTHING_FACTOR: f32 = 1.0 / 8.0;
+
+/// Timer for sending pings to the server.
+#[derive(Resource, Deref, DerefMut)]
+pub struct PingTimer(pub Timer);
+
+impl Default for PingTimer {
+    fn default() -> Self {
+        PingTimer(Timer::new(PING_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+/// Round trip time to the server, measured with [PlayerMessage::Ping] messages.
+///
+/// # Fields
+///
+/// * `pending` - When the unanswered pings were sent. The server answers in the same order, so the first
+///   entry belongs to the next pong.
+/// * `rtt` - Smoothed round trip time. `None` until the first pong arrived.
+/// * `jitter` - Smoothed difference between two consecutive round trip times.
+#[derive(Resource, Default)]
+pub struct Latency {
+    pub pending: VecDeque<Duration>,
+    pub rtt: Option<Duration>,
+    pub jitter: Duration,
+}
+
+impl Latency {
+    /// Adds a new round trip time measurement.
+    fn add_sample(&mut self, sample: Duration) {
+        let Some(rtt) = self.rtt else {
+            self.rtt = Some(sample);
+            return;
+        };
+
+        let difference = sample.as_secs_f32() - rtt.as_secs_f32();
+        let jitter = self.jitter.as_secs_f32();
+        self.jitter = Duration::from_secs_f32(jitter + (difference.abs() - jitter) * SMOOTHING_FACTOR);
+        self.rtt = Some(Duration::from_secs_f32(
+            rtt.as_secs_f32() + difference * SMOOTHING_FACTOR,
+        ));
+    }
+}
+
+/// Bevy event to be fired when the server answered a ping.
+#[derive(Event)]
+pub struct PongReceivedEvent;
+
+/// Sends a [PlayerMessage::Ping] message to the server every [`PING_INTERVAL`].
+pub fn send_ping(
+    time: Res<Time<Real>>,
+    mut timer: ResMut<PingTimer>,
+    mut latency: ResMut<Latency>,
+    client: Res<Client>,
+) {
+    timer.tick(time.delta());
+    if !timer.just_finished() || latency.pending.len() >= MAX_PENDING_PINGS {
+        return;
+    }
+
+    client.connection().try_send_message(PlayerMessage::Ping);
+    latency.pending.push_back(time.elapsed());
+}
+
+/// Called when the server answered a ping. Measures the round trip time of the oldest pending ping.
+pub fn on_pong_received(
+    mut events: EventReader<PongReceivedEvent>,
+    time: Res<Time<Real>>,
+    mut latency: ResMut<Latency>,
+) {
+    for _ in events.read() {
+        if let Some(sent_at) = latency.pending.pop_front() {
+            latency.add_sample(time.elapsed().saturating_sub(sent_at));
+        }
+    }
+}
+
+/// Forgets the measurements of the previous connection whenever a new connection was established.
+pub fn reset_latency(
+    mut connection_event: EventReader<ConnectionEvent>,
+    mut latency: ResMut<Latency>,
+) {
+    if !connection_event.is_empty() {
+        connection_event.clear();
+        *latency = Latency::default();
+    }
+}
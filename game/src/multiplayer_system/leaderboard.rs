@@ -0,0 +1,29 @@
+use bevy::ecs::{
+    event::{Event, EventReader},
+    system::Res,
+};
+use bevy_quinnet::client::Client;
+use shared::{Leaderboard, PlayerMessage};
+
+use crate::asset_system::levels::CurrentLevel;
+use crate::score_system::leaderboard::LeaderboardOpenedEvent;
+
+/// Bevy event to be fired when server sends the leaderboard of a level.
+#[derive(Event)]
+pub struct LeaderboardInfoEvent(pub Leaderboard);
+
+/// Called when the player opens the leaderboard screen. Requests the leaderboard of the current level from the server,
+/// which answers with a [`ServerMessage::Leaderboard`] message.
+pub fn on_leaderboard_opened(
+    mut events: EventReader<LeaderboardOpenedEvent>,
+    client: Res<Client>,
+    current_level: Res<CurrentLevel>,
+) {
+    for _ in events.read() {
+        if let Some(level_iid) = current_level.iid.clone() {
+            client
+                .connection()
+                .try_send_message(PlayerMessage::RequestLeaderboard(level_iid));
+        }
+    }
+}
@@ -1,4 +1,17 @@
+pub mod certificate;
+pub mod chat;
+pub mod connect_screen;
 pub mod connection;
 mod ghost_player;
+pub mod handshake;
 pub mod highscore;
+pub mod interpolation;
+pub mod latency;
+pub mod leaderboard;
 mod player_movement;
+pub mod race;
+pub mod reconnect;
+pub mod room_screen;
+pub mod roster;
+pub mod server_address;
+pub mod spectator;
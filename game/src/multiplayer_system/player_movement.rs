@@ -1,17 +1,18 @@
 use bevy::{
     ecs::{
         query::With,
-        system::{Query, Res, ResMut, Resource},
+        system::{Local, Query, Res, ResMut, Resource},
     },
     prelude::{Deref, DerefMut},
     time::{Time, Timer},
     transform::components::GlobalTransform,
 };
-use bevy_quinnet::client::Client;
+use bevy_quinnet::{client::Client, shared::channel::ChannelId};
 use bevy_rapier2d::dynamics::Velocity;
-use shared::{PlayerMessage, PlayerMovement};
+use shared::{MovementUpdate, PlayerMessage, PlayerMovement};
 
 use crate::asset_system::players::Player;
+use crate::multiplayer_system::ghost_player::SnapshotHistory;
 
 /// Timer for sending updates to the server about the movement of the player.
 #[derive(Resource, Deref, DerefMut)]
@@ -21,9 +22,13 @@ pub struct UpdatePlayerMovementTimer(pub Timer);
 ///
 /// To decrease server load the message is only sent when the [UpdatePlayerMovementTimer]
 /// is finished. Check the [setup_client] function for the exact timer interval.
+/// The messages are numbered and sent on the unreliable channel, the server drops the ones arriving out of order.
+/// Each message acknowledges the newest snapshot in the [SnapshotHistory].
 pub fn update_player_movement(
     time: Res<Time>,
     mut timer: ResMut<UpdatePlayerMovementTimer>,
+    mut sequence: Local<u32>,
+    history: Res<SnapshotHistory>,
     client: Res<Client>,
     query: Query<(&Velocity, &GlobalTransform), With<Player>>,
 ) {
@@ -40,8 +45,14 @@ pub fn update_player_movement(
             translation_y: transform.translation().y,
         };
 
-        client
-            .connection()
-            .try_send_message(PlayerMessage::PlayerMoved(movement));
+        *sequence += 1;
+        client.connection().try_send_message_on(
+            ChannelId::Unreliable,
+            PlayerMessage::PlayerMoved(MovementUpdate {
+                sequence: *sequence,
+                ack_tick: history.latest_tick(),
+                movement,
+            }),
+        );
     }
 }
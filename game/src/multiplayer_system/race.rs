@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::Real;
+use bevy_quinnet::client::Client;
+use bevy_rapier2d::prelude::Velocity;
+use shared::{PlayerMessage, RacePlacing, SPAWN_POSITION};
+
+use crate::asset_system::players::Player;
+use crate::input_system::input_handler::InputHandler;
+use crate::multiplayer_system::interpolation::ServerClock;
+use crate::multiplayer_system::room_screen::RoomJoinedEvent;
+use crate::score_system::time::{RunStartedEvent, TimeText};
+
+/// Key used to ready up for the next race or take it back.
+const READY_BUTTON: KeyCode = KeyCode::R;
+
+/// State of the race in the room of the player.
+///
+/// # Fields
+///
+/// * `ready` - If the player is ready for the next race.
+/// * `ready_players` - Ids of all players in the room who are ready.
+/// * `start_at` - Real time of the game at which the race starts. Set while the countdown runs, the input
+///   of the player is locked until then.
+/// * `started_at` - Real time of the game at which the last race started.
+/// * `results` - Finishing order of the last race, as announced by the server.
+#[derive(Resource, Default)]
+pub struct RaceState {
+    pub ready: bool,
+    pub ready_players: Vec<u64>,
+    pub start_at: Option<Duration>,
+    pub started_at: Option<Duration>,
+    pub results: Vec<RacePlacing>,
+}
+
+/// Bevy event to be fired when the server sends which players are ready for the next race.
+#[derive(Event)]
+pub struct RaceReadyEvent(pub Vec<u64>);
+
+/// Bevy event to be fired when the server starts the countdown. Contains the time of the server at which
+/// the race starts.
+#[derive(Event)]
+pub struct RaceCountdownEvent(pub Duration);
+
+/// Bevy event to be fired when the server announces the finishing order of the race.
+#[derive(Event)]
+pub struct RaceResultsEvent(pub Vec<RacePlacing>);
+
+/// Readies the player up for the next race when the ready key is pressed, or takes it back when pressed again.
+pub fn toggle_ready(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<RaceState>,
+    client: Res<Client>,
+) {
+    if !keyboard_input.just_pressed(READY_BUTTON) || state.start_at.is_some() {
+        return;
+    }
+
+    state.ready = !state.ready;
+    client
+        .connection()
+        .try_send_message(PlayerMessage::SetRaceReady(state.ready));
+}
+
+/// Handles the race messages of the server.
+///
+/// When the countdown starts the player is put back to the start and stopped. The start time of the server is
+/// converted to the real time of the game with the [`ServerClock`], so all players start at the same instant.
+/// Entering another room forgets the race of the old room.
+pub fn on_race_messages(
+    time: Res<Time<Real>>,
+    server_clock: Res<ServerClock>,
+    mut ready_events: EventReader<RaceReadyEvent>,
+    mut countdown_events: EventReader<RaceCountdownEvent>,
+    mut results_events: EventReader<RaceResultsEvent>,
+    mut room_events: EventReader<RoomJoinedEvent>,
+    mut state: ResMut<RaceState>,
+    mut player: Query<(&mut Transform, &mut Velocity, &mut InputHandler), With<Player>>,
+) {
+    // Bevy systems get one argument per event they read.
+    #![allow(clippy::too_many_arguments)]
+    if room_events.read().count() > 0 {
+        *state = RaceState::default();
+    }
+
+    for ev in ready_events.read() {
+        state.ready_players = ev.0.clone();
+    }
+
+    for ev in countdown_events.read() {
+        let now = time.elapsed();
+        // Without any snapshot yet the time of the server is unknown, so the race starts right away
+        let remaining = server_clock
+            .server_time(now)
+            .map(|server_now| ev.0.saturating_sub(server_now))
+            .unwrap_or_default();
+        info!(countdown_secs = remaining.as_secs_f32(), "Race starts.");
+
+        state.ready = false;
+        state.start_at = Some(now + remaining);
+        state.results.clear();
+
+        for (mut transform, mut velocity, mut input_handler) in player.iter_mut() {
+            // The players wait at the spawn point until the race starts
+            transform.translation = Vec2::from_array(SPAWN_POSITION).extend(transform.translation.z);
+            *velocity = Velocity::zero();
+            *input_handler = InputHandler::default();
+        }
+    }
+
+    for ev in results_events.read() {
+        state.results = ev.0.clone();
+    }
+}
+
+/// Starts the race once the countdown is over. Resets the time, so it shows the time since the start, and
+/// starts a new run, which the server measures and reports once the player crosses the finish line.
+pub fn start_race(
+    time: Res<Time<Real>>,
+    mut state: ResMut<RaceState>,
+    mut time_text: Query<&mut TimeText, With<TimeText>>,
+    mut run_started_events: EventWriter<RunStartedEvent>,
+) {
+    let now = time.elapsed();
+    let Some(start_at) = state.start_at else {
+        return;
+    };
+    if now < start_at {
+        return;
+    }
+
+    state.start_at = None;
+    state.started_at = Some(now);
+    for mut time_text in time_text.iter_mut() {
+        time_text.time.reset();
+    }
+    run_started_events.send(RunStartedEvent);
+}
+
+/// Run condition which is false during the countdown of a race. Used to lock the input of the player until the start.
+pub fn is_input_unlocked(state: Res<RaceState>) -> bool {
+    state.start_at.is_none()
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_quinnet::client::{connection::ConnectionLostEvent, Client};
+use shared::SessionInfo;
+
+use crate::multiplayer_system::certificate::CertificateTrust;
+use crate::multiplayer_system::connect_screen::ConnectScreenState;
+use crate::multiplayer_system::connection::{open_connection, ConnectionStatus};
+use crate::multiplayer_system::server_address::ServerAddress;
+
+/// Time to wait before the first reconnect attempt. Doubles with every failed attempt.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest time to wait between two reconnect attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Token of the current session on the server, sent when joining again after the connection was lost.
+#[derive(Resource, Default)]
+pub struct SessionToken(pub Option<String>);
+
+/// State of the reconnect attempts after the connection to the server was lost.
+///
+/// # Fields
+///
+/// * `attempt` - Number of reconnect attempts since the connection was lost.
+/// * `timer` - Counts down to the next attempt. `None` if no reconnect is scheduled.
+#[derive(Resource, Default)]
+pub struct ReconnectState {
+    pub attempt: u32,
+    pub timer: Option<Timer>,
+}
+
+impl ReconnectState {
+    /// Stops reconnecting, e.g. because the player chose another server.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+        self.timer = None;
+    }
+
+    /// The delay before the given attempt: 1s, 2s, 4s, ... up to [`MAX_RECONNECT_DELAY`].
+    fn delay(attempt: u32) -> Duration {
+        INITIAL_RECONNECT_DELAY
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_RECONNECT_DELAY)
+    }
+}
+
+/// Bevy event to be fired when the server accepted the join request.
+#[derive(Event)]
+pub struct JoinAcceptedEvent(pub SessionInfo);
+
+/// Called when the player joined the game. Stores the session token and stops reconnecting.
+pub fn on_join_accepted(
+    mut events: EventReader<JoinAcceptedEvent>,
+    mut session_token: ResMut<SessionToken>,
+    mut reconnect_state: ResMut<ReconnectState>,
+) {
+    for ev in events.read() {
+        if ev.0.resumed {
+            info!(player_id = ev.0.player_id, "Resumed the previous session after reconnecting.");
+        } else {
+            info!(player_id = ev.0.player_id, "Joined the game.");
+        }
+        session_token.0 = Some(ev.0.session_token.clone());
+        reconnect_state.reset();
+    }
+}
+
+/// Called when the connection to the server was lost or could not be established. Schedules the next
+/// reconnect attempt with an exponentially growing delay. Does not reconnect while the connect screen is open,
+/// e.g. because the certificate of the server was rejected.
+pub fn schedule_reconnect(
+    mut connection_lost_event: EventReader<ConnectionLostEvent>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    connect_screen: Res<ConnectScreenState>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    if connection_lost_event.is_empty() {
+        return;
+    }
+    connection_lost_event.clear();
+    if connect_screen.open {
+        reconnect_state.reset();
+        *status = ConnectionStatus::Offline;
+        return;
+    }
+
+    reconnect_state.attempt += 1;
+    let delay = ReconnectState::delay(reconnect_state.attempt);
+    warn!(
+        delay_secs = delay.as_secs(),
+        attempt = reconnect_state.attempt,
+        "Connection to server lost, reconnecting."
+    );
+    reconnect_state.timer = Some(Timer::new(delay, TimerMode::Once));
+    *status = ConnectionStatus::Disconnected;
+}
+
+/// Opens a new connection to the server once the delay of the scheduled reconnect attempt passed.
+/// After connecting the game joins with the [`SessionToken`], so the server can resume the session.
+pub fn reconnect(
+    time: Res<Time>,
+    mut reconnect_state: ResMut<ReconnectState>,
+    mut client: ResMut<Client>,
+    server_address: Res<ServerAddress>,
+    trust: Res<CertificateTrust>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    let Some(timer) = reconnect_state.timer.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).finished() {
+        return;
+    }
+    reconnect_state.timer = None;
+
+    if let Err(error) = client.close_all_connections() {
+        error!(%error, "Error closing connection to server.");
+    }
+    open_connection(&mut client, &server_address, &trust, &mut status);
+}
@@ -0,0 +1,303 @@
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use bevy_quinnet::client::Client;
+use shared::{CreateRoomRequest, JoinRoomRequest, JoinedRoom, PlayerMessage, RoomInfo};
+
+use crate::asset_system::players::Player;
+use crate::input_system::input_handler::InputHandler;
+use crate::multiplayer_system::connect_screen::ConnectScreenState;
+
+/// Key used to open the room screen.
+const OPEN_BUTTON: KeyCode = KeyCode::F3;
+
+/// Maximum number of characters of the typed command.
+const MAX_INPUT_LENGTH: usize = 48;
+
+/// Commands understood by the room screen, shown below the input.
+const HELP: &str = "create <name> | private <name> | join <id> [code] | leave\nEnter: send   Esc: close";
+
+/// This component marks the root node of the room screen.
+#[derive(Component)]
+pub struct RoomScreen;
+
+/// This component marks the text of the room screen.
+#[derive(Component)]
+pub struct RoomScreenText;
+
+/// State of the room screen.
+///
+/// # Fields
+///
+/// * `open` - If the room screen is currently shown.
+/// * `input` - The command typed by the player.
+/// * `rooms` - The rooms last sent by the server.
+/// * `current` - The room the player is in. `None` until the server sent it.
+/// * `error` - Why the last command could not be used or was rejected by the server.
+#[derive(Resource, Default)]
+pub struct RoomScreenState {
+    pub open: bool,
+    pub input: String,
+    pub rooms: Vec<RoomInfo>,
+    pub current: Option<JoinedRoom>,
+    pub error: Option<String>,
+}
+
+/// Bevy event to be fired when the server sends the list of rooms.
+#[derive(Event)]
+pub struct RoomListEvent(pub Vec<RoomInfo>);
+
+/// Bevy event to be fired when the player entered a room.
+#[derive(Event)]
+pub struct RoomJoinedEvent(pub JoinedRoom);
+
+/// Bevy event to be fired when the server rejected creating or joining a room. Contains the reason.
+#[derive(Event)]
+pub struct RoomRejectedEvent(pub String);
+
+/// Sets up the room screen
+///
+/// This function is responsible for spawning the entities of the room screen in the ECS.
+/// It spawns a hidden `NodeBundle` entity with a `RoomScreen` component that covers the center of the window,
+/// and a `TextBundle` child with a `RoomScreenText` component that shows the rooms and the typed command.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(15.0),
+                    left: Val::Percent(20.0),
+                    width: Val::Percent(60.0),
+                    padding: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            RoomScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/Pixelfont.ttf"),
+                        font_size: 24.0,
+                        ..default()
+                    },
+                ),
+                RoomScreenText,
+            ));
+        });
+}
+
+/// Opens and closes the room screen
+///
+/// This function opens the room screen when the open key is pressed while the connect screen is closed,
+/// and asks the server for the current list of rooms. The screen is closed again with escape.
+/// Keys held while the screen opens are released.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `state` - The `RoomScreenState` resource.
+/// * `connect_screen` - The `ConnectScreenState` resource.
+/// * `client` - The client used to request the list of rooms.
+/// * `player` - The input handler of the player.
+pub fn toggle_room_screen(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<RoomScreenState>,
+    connect_screen: Res<ConnectScreenState>,
+    client: Res<Client>,
+    mut player: Query<&mut InputHandler, With<Player>>,
+) {
+    if !state.open && !connect_screen.open && keyboard_input.just_pressed(OPEN_BUTTON) {
+        state.open = true;
+        state.input.clear();
+        state.error = None;
+        for mut input_handler in player.iter_mut() {
+            *input_handler = InputHandler::default();
+        }
+        send_to_server(&client, PlayerMessage::ListRooms);
+    } else if state.open && keyboard_input.just_pressed(KeyCode::Escape) {
+        state.open = false;
+    }
+}
+
+/// Handles typing on the room screen
+///
+/// This function appends the typed characters to the command, removes the last one on backspace
+/// and sends the command to the server on enter if it is valid. The list of rooms is requested again
+/// after every command, so it shows the new player counts.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `characters` - An `EventReader` for the characters typed by the player.
+/// * `state` - The `RoomScreenState` resource.
+/// * `client` - The client used to send the command.
+pub fn type_room_command(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut state: ResMut<RoomScreenState>,
+    client: Res<Client>,
+) {
+    if !state.open {
+        characters.clear();
+        return;
+    }
+
+    for ev in characters.read() {
+        let allowed = ev.char.is_alphanumeric() || " -_".contains(ev.char);
+        if allowed && state.input.chars().count() < MAX_INPUT_LENGTH {
+            state.input.push(ev.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        state.input.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        match parse_room_command(&state.input) {
+            Ok(message) => {
+                state.input.clear();
+                state.error = None;
+                send_to_server(&client, message);
+                send_to_server(&client, PlayerMessage::ListRooms);
+            }
+            Err(error) => state.error = Some(error),
+        }
+    }
+}
+
+/// Stores the rooms and the current room sent by the server and shows rejected requests on the room screen.
+pub fn on_room_messages(
+    mut list_events: EventReader<RoomListEvent>,
+    mut joined_events: EventReader<RoomJoinedEvent>,
+    mut rejected_events: EventReader<RoomRejectedEvent>,
+    mut state: ResMut<RoomScreenState>,
+) {
+    for ev in list_events.read() {
+        state.rooms = ev.0.clone();
+    }
+    for ev in joined_events.read() {
+        info!(room_id = ev.0.id, name = %ev.0.name, "Entered room.");
+        state.current = Some(ev.0.clone());
+    }
+    for ev in rejected_events.read() {
+        warn!(reason = %ev.0, "Server rejected the room request.");
+        state.error = Some(ev.0.clone());
+    }
+}
+
+/// Updates the room screen
+///
+/// This function shows or hides the room screen and updates its `Text` component whenever the
+/// `RoomScreenState` changed. The join code of the current room is shown, so it can be passed on
+/// to the other players.
+///
+/// # Arguments
+///
+/// * `state` - The `RoomScreenState` resource.
+/// * `screens` - A `Query` that fetches the `Visibility` component of the room screen.
+/// * `query` - A `Query` that fetches the `Text` component of the room screen text entity.
+pub fn update_room_screen(
+    state: Res<RoomScreenState>,
+    mut screens: Query<&mut Visibility, With<RoomScreen>>,
+    mut query: Query<&mut Text, With<RoomScreenText>>,
+) {
+    if !state.is_changed() {
+        return;
+    }
+
+    for mut visibility in screens.iter_mut() {
+        *visibility = if state.open {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    let current = match &state.current {
+        Some(JoinedRoom {
+            name,
+            join_code: Some(join_code),
+            ..
+        }) => format!("You are in {} (join code {})", name, join_code),
+        Some(room) => format!("You are in {}", room.name),
+        None => "You are in no room".to_string(),
+    };
+    let rooms: Vec<String> = state
+        .rooms
+        .iter()
+        .map(|room| {
+            format!(
+                "{:>3}  {}  ({} players){}",
+                room.id,
+                room.name,
+                room.players,
+                if room.private { "  private" } else { "" }
+            )
+        })
+        .collect();
+    let hint = state.error.clone().unwrap_or_else(|| HELP.to_string());
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!(
+            "Rooms\n{}\n\n{}\n\n> {}_\n\n{}",
+            current,
+            rooms.join("\n"),
+            state.input,
+            hint
+        );
+    }
+}
+
+/// Run condition which is true while the room screen is closed. Used to ignore game input while typing.
+pub fn is_room_screen_closed(state: Res<RoomScreenState>) -> bool {
+    !state.open
+}
+
+/// Turns a typed command into the message for the server. Returns the reason if the command is invalid.
+fn parse_room_command(input: &str) -> Result<PlayerMessage, String> {
+    let input = input.trim();
+    let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+    let argument = argument.trim();
+
+    match command {
+        "create" | "private" if !argument.is_empty() => {
+            Ok(PlayerMessage::CreateRoom(CreateRoomRequest {
+                name: argument.to_string(),
+                private: command == "private",
+            }))
+        }
+        "create" | "private" => Err("The room needs a name.".to_string()),
+        "join" => {
+            let mut parts = argument.split_whitespace();
+            let room_id = parts
+                .next()
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| "Type the id of the room to join.".to_string())?;
+            Ok(PlayerMessage::JoinRoom(JoinRoomRequest {
+                room_id,
+                join_code: parts.next().map(str::to_string),
+            }))
+        }
+        "leave" => Ok(PlayerMessage::LeaveRoom),
+        _ => Err(format!("Unknown command `{}`", command)),
+    }
+}
+
+/// Sends the message if the player is connected to a server.
+fn send_to_server(client: &Client, message: PlayerMessage) {
+    if let Some(connection) = client.get_connection() {
+        connection.try_send_message(message);
+    }
+}
@@ -0,0 +1,103 @@
+use std::env;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use shared::RosterEntry;
+
+use crate::asset_system::players::GhostPlayer;
+
+/// Environment variable to set the display name of the player.
+const PLAYER_NAME_ENV: &str = "PLAYER_NAME";
+
+/// Command line argument to set the display name of the player, e.g. `--name Speedy`.
+const PLAYER_NAME_ARG: &str = "--name";
+
+/// Name used when the player did not choose one.
+const DEFAULT_PLAYER_NAME: &str = "Player";
+
+/// Display name of the local player, sent to the server when joining the game.
+#[derive(Resource, Deref)]
+pub struct PlayerName(pub String);
+
+impl PlayerName {
+    /// Reads the name from the `--name` command line argument or the `PLAYER_NAME` environment variable.
+    /// Falls back to `Player` if neither is set.
+    pub fn from_args_or_env() -> Self {
+        let mut args = env::args().skip_while(|arg| arg != PLAYER_NAME_ARG).skip(1);
+        let name = args
+            .next()
+            .or_else(|| env::var(PLAYER_NAME_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_PLAYER_NAME.to_string());
+        PlayerName(name)
+    }
+}
+
+/// Names of all players in the game, keyed by their id.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Roster(pub HashMap<u64, String>);
+
+/// Component for the text showing the name above a ghost player.
+#[derive(Component)]
+pub struct NameTag {
+    pub player_id: u64,
+}
+
+/// Bevy event to be fired when server sends the names of the players in the game.
+#[derive(Event)]
+pub struct RosterUpdatedEvent(pub Vec<RosterEntry>);
+
+/// Replaces the [`Roster`] with the one sent by the server.
+pub fn on_roster_updated(mut events: EventReader<RosterUpdatedEvent>, mut roster: ResMut<Roster>) {
+    for ev in events.read() {
+        roster.0 = ev
+            .0
+            .iter()
+            .map(|entry| (entry.id, entry.name.clone()))
+            .collect();
+    }
+}
+
+/// Spawns a name tag above every newly spawned ghost player.
+pub fn spawn_name_tags(
+    mut commands: Commands,
+    ghosts: Query<(Entity, &GhostPlayer), Added<GhostPlayer>>,
+    roster: Res<Roster>,
+    asset_server: Res<AssetServer>,
+) {
+    for (entity, ghost_player) in &ghosts {
+        let name = roster.get(&ghost_player.id).cloned().unwrap_or_default();
+
+        commands.entity(entity).with_children(|builder| {
+            builder.spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        name,
+                        TextStyle {
+                            font: asset_server.load("fonts/Pixelfont.ttf"),
+                            font_size: 10.0,
+                            ..default()
+                        },
+                    ),
+                    transform: Transform::from_xyz(0.0, 14.0, 1.0),
+                    ..default()
+                },
+                NameTag {
+                    player_id: ghost_player.id,
+                },
+            ));
+        });
+    }
+}
+
+/// Updates the text of all name tags when the [`Roster`] changed.
+pub fn update_name_tags(roster: Res<Roster>, mut name_tags: Query<(&NameTag, &mut Text)>) {
+    if !roster.is_changed() {
+        return;
+    }
+
+    for (name_tag, mut text) in name_tags.iter_mut() {
+        if let Some(name) = roster.get(&name_tag.player_id) {
+            text.sections[0].value = name.clone();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    env, fs, io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Environment variable to set the address of the server, e.g. `SERVER_ADDR=192.168.0.10:8123`.
+const SERVER_ADDR_ENV: &str = "SERVER_ADDR";
+
+/// Command line argument to set the address of the server, e.g. `--server 192.168.0.10:8123`.
+const SERVER_ADDR_ARG: &str = "--server";
+
+/// Environment variable to overwrite the path of the settings file.
+const SETTINGS_FILE_ENV: &str = "SETTINGS_FILE";
+
+/// Path of the settings file used when [`SETTINGS_FILE_ENV`] is not set.
+const DEFAULT_SETTINGS_FILE: &str = "settings.json";
+
+/// Address used when the player did not choose one. Use `127.0.0.1` when running the server locally.
+const DEFAULT_SERVER_ADDR: &str = "127.0.0.1";
+
+/// Port used when the address does not contain one.
+const DEFAULT_SERVER_PORT: u16 = 8123;
+
+/// Address of the server the client connects to.
+#[derive(Resource, Clone)]
+pub struct ServerAddress {
+    /// The address as the player entered it, e.g. a hostname without port.
+    pub input: String,
+    pub addr: SocketAddr,
+}
+
+impl ServerAddress {
+    /// Reads the address from the `--server` command line argument, the `SERVER_ADDR` environment variable
+    /// or the settings file, in this order. Falls back to `127.0.0.1:8123` if neither is set or valid.
+    pub fn from_args_env_or_settings() -> Self {
+        let mut args = env::args().skip_while(|arg| arg != SERVER_ADDR_ARG).skip(1);
+        let input = args
+            .next()
+            .or_else(|| env::var(SERVER_ADDR_ENV).ok())
+            .or_else(|| load_settings().server_addr);
+
+        if let Some(input) = input {
+            match ServerAddress::parse(&input) {
+                Ok(server_address) => return server_address,
+                Err(error) => warn!("{}, using {} instead.", error, DEFAULT_SERVER_ADDR),
+            }
+        }
+        ServerAddress::parse(DEFAULT_SERVER_ADDR).expect("Default server address should be valid")
+    }
+
+    /// Parses an ip address or hostname with an optional port. Addresses without port use port 8123.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let addr = if let Ok(addr) = input.parse::<SocketAddr>() {
+            Some(addr)
+        } else if let Ok(ip) = input.parse::<IpAddr>() {
+            Some(SocketAddr::new(ip, DEFAULT_SERVER_PORT))
+        } else if input.contains(':') {
+            resolve(input)
+        } else {
+            resolve((input, DEFAULT_SERVER_PORT))
+        };
+
+        match addr {
+            Some(addr) => Ok(ServerAddress {
+                input: input.to_string(),
+                addr,
+            }),
+            None => Err(format!("`{}` is no valid server address", input)),
+        }
+    }
+
+    /// Stores the address in the settings file, so it is used the next time the game starts.
+    pub fn remember(&self) {
+        let settings = Settings {
+            server_addr: Some(self.input.clone()),
+        };
+        if let Err(error) = save_settings(&settings) {
+            let path = settings_path();
+            error!(path = %path.display(), %error, "Failed to save settings.");
+        }
+    }
+}
+
+/// Looks up a hostname and returns its first address.
+fn resolve(host: impl ToSocketAddrs) -> Option<SocketAddr> {
+    host.to_socket_addrs().ok().and_then(|mut addrs| addrs.next())
+}
+
+/// Content of the settings file.
+#[derive(Serialize, Deserialize, Default)]
+struct Settings {
+    server_addr: Option<String>,
+}
+
+fn settings_path() -> PathBuf {
+    env::var_os(SETTINGS_FILE_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SETTINGS_FILE))
+}
+
+/// Reads the settings file. A missing or broken file results in the default settings.
+fn load_settings() -> Settings {
+    let path = settings_path();
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+            warn!(path = %path.display(), %error, "Ignoring invalid settings file.");
+            Settings::default()
+        }),
+        Err(_) => Settings::default(),
+    }
+}
+
+fn save_settings(settings: &Settings) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(settings)?;
+    fs::write(settings_path(), content)
+}
@@ -0,0 +1,192 @@
+use std::env;
+
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use bevy::time::Real;
+
+use crate::asset_system::players::GhostPlayer;
+use crate::multiplayer_system::roster::Roster;
+
+/// Environment variable to start the game as spectator, e.g. `SPECTATE=1`.
+const SPECTATE_ENV: &str = "SPECTATE";
+
+/// Command line argument to start the game as spectator.
+const SPECTATE_ARG: &str = "--spectate";
+
+/// Key used to follow the next player, after the last player the camera is free again.
+const FOLLOW_BUTTON: KeyCode = KeyCode::F;
+
+/// Speed of the free camera in pixels per second.
+const PAN_SPEED: f32 = 300.0;
+
+/// If the game watches the other players instead of playing. Spectators spawn no local player,
+/// are not shown to the other players and control the camera instead.
+#[derive(Resource, Clone, Copy, PartialEq, Eq)]
+pub struct SpectatorMode(pub bool);
+
+impl SpectatorMode {
+    /// Reads the mode from the `--spectate` command line argument or the `SPECTATE` environment variable.
+    /// Anything but `0` and `false` in the environment variable enables it.
+    pub fn from_args_or_env() -> Self {
+        let arg = env::args().any(|arg| arg == SPECTATE_ARG);
+        let var = env::var(SPECTATE_ENV)
+            .is_ok_and(|value| !matches!(value.trim(), "" | "0" | "false"));
+        SpectatorMode(arg || var)
+    }
+}
+
+/// Camera of the spectator.
+///
+/// # Fields
+///
+/// * `following` - Id of the player the camera follows. `None` while the camera is moved freely.
+#[derive(Resource, Default)]
+pub struct SpectatorCamera {
+    pub following: Option<u64>,
+}
+
+/// This component marks the text showing whom the spectator is watching.
+#[derive(Component)]
+pub struct SpectatorText;
+
+/// Sets up the spectator display
+///
+/// This function is responsible for spawning the entity of the spectator display in the ECS.
+/// It spawns an empty `TextBundle` entity in the lower right corner of the window with a `SpectatorText` component.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Pixelfont.ttf"),
+                font_size: 20.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            right: Val::Px(20.0),
+            ..default()
+        }),
+        SpectatorText,
+    ));
+}
+
+/// Switches to the next player to follow
+///
+/// This function lets the camera follow the ghost player with the next higher id when the follow key is pressed.
+/// After the player with the highest id the camera is free again. A followed player who left frees the camera as well.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `spectator_camera` - The `SpectatorCamera` resource.
+/// * `ghosts` - A `Query` that fetches all ghost players.
+pub fn cycle_followed_player(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut spectator_camera: ResMut<SpectatorCamera>,
+    ghosts: Query<&GhostPlayer>,
+) {
+    let mut ids: Vec<u64> = ghosts.iter().map(|ghost| ghost.id).collect();
+    ids.sort();
+
+    if let Some(id) = spectator_camera.following {
+        if !ids.contains(&id) {
+            spectator_camera.following = None;
+        }
+    }
+    if !keyboard_input.just_pressed(FOLLOW_BUTTON) {
+        return;
+    }
+
+    spectator_camera.following = match spectator_camera.following {
+        Some(id) => ids.into_iter().find(|other| *other > id),
+        None => ids.first().copied(),
+    };
+}
+
+/// Moves the camera of the spectator
+///
+/// This function sets the camera's x position to the followed ghost player, like the camera of a playing game does
+/// with the local player. While no player is followed the camera is moved with the arrow keys or WASD.
+///
+/// # Arguments
+///
+/// * `time` - The real time of the game, used to move the free camera at the same speed on every frame rate.
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `spectator_camera` - The `SpectatorCamera` resource.
+/// * `ghosts` - A `Query` that fetches the transforms of all ghost players.
+/// * `camera` - A `Query` that fetches the camera's transform.
+pub fn move_spectator_camera(
+    time: Res<Time<Real>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    spectator_camera: Res<SpectatorCamera>,
+    ghosts: Query<(&GhostPlayer, &Transform)>,
+    mut camera: Query<&mut Transform, (With<Camera2d>, Without<GhostPlayer>)>,
+) {
+    if let Some(id) = spectator_camera.following {
+        let Some((_, ghost_transform)) = ghosts.iter().find(|(ghost, _)| ghost.id == id) else {
+            return;
+        };
+        for mut transform in camera.iter_mut() {
+            transform.translation.x = ghost_transform.translation.x;
+        }
+        return;
+    }
+
+    let pressed = |keys: [KeyCode; 2]| keyboard_input.any_pressed(keys) as i32 as f32;
+    let direction = Vec2::new(
+        pressed([KeyCode::Right, KeyCode::D]) - pressed([KeyCode::Left, KeyCode::A]),
+        pressed([KeyCode::Up, KeyCode::W]) - pressed([KeyCode::Down, KeyCode::S]),
+    );
+    let offset = direction * PAN_SPEED * time.delta_seconds();
+    for mut transform in camera.iter_mut() {
+        transform.translation += offset.extend(0.0);
+    }
+}
+
+/// Updates the spectator display
+///
+/// This function shows the name of the followed player, or that the camera is free, together with the keys to use.
+///
+/// # Arguments
+///
+/// * `spectator_camera` - The `SpectatorCamera` resource.
+/// * `roster` - The `Roster` resource with the names of all players in the room.
+/// * `query` - A `Query` that fetches the `Text` component of the spectator display.
+pub fn update_spectator_label(
+    spectator_camera: Res<SpectatorCamera>,
+    roster: Res<Roster>,
+    mut query: Query<&mut Text, With<SpectatorText>>,
+) {
+    let value = match spectator_camera.following {
+        Some(id) => format!(
+            "Spectating {} - F: next player",
+            roster.get(&id).map_or("...", |name| name.as_str())
+        ),
+        None => "Free camera - arrows to move, F: follow a player".to_string(),
+    };
+
+    for mut text in query.iter_mut() {
+        // Only touch the text when it changed, so it is not laid out again every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+/// Run condition which is true if the game is a spectator.
+pub fn is_spectating(mode: Res<SpectatorMode>) -> bool {
+    mode.0
+}
+
+/// Run condition which is true if the game plays. Used to ignore the controls of a player while spectating.
+pub fn is_playing(mode: Res<SpectatorMode>) -> bool {
+    !mode.0
+}
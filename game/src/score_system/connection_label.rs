@@ -0,0 +1,104 @@
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+
+use crate::multiplayer_system::connection::ConnectionStatus;
+use crate::multiplayer_system::interpolation::ServerClock;
+use crate::multiplayer_system::latency::Latency;
+use crate::multiplayer_system::reconnect::ReconnectState;
+use crate::multiplayer_system::server_address::ServerAddress;
+/// This component marks the text showing the state of the connection to the server.
+#[derive(Component)]
+pub struct ConnectionText;
+/// Sets up the initial state of the connection display
+///
+/// This function is responsible for spawning the initial entities in the ECS for the connection display.
+/// It spawns a `TextBundle` entity with a `Text` component below the highscore display,
+/// and a `ConnectionText` component to find it again.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+///
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Pixelfont.ttf"),
+                font_size: 25.0,
+                ..default()
+            },
+        )
+        .with_text_alignment(TextAlignment::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            left: Val::Px(20.0),
+            ..default()
+        }),
+        ConnectionText,
+    ));
+}
+/// Updates the connection display
+///
+/// This function is responsible for showing the `ConnectionStatus` in the `Text` component of the
+/// connection display. While connected it shows the round trip time and jitter measured by pinging the server
+/// and the share of lost updates, while disconnected the seconds until the next reconnect attempt.
+///
+/// # Arguments
+///
+/// * `status` - The `ConnectionStatus` resource.
+/// * `latency` - The `Latency` resource with the measured round trip time.
+/// * `server_clock` - The `ServerClock` resource with the measured packet loss.
+/// * `reconnect_state` - The `ReconnectState` resource with the timer of the next reconnect attempt.
+/// * `server_address` - The address of the server the client connects to.
+/// * `query` - A `Query` that fetches the `Text` component of the connection display entity.
+pub fn update_connection_label(
+    status: Res<ConnectionStatus>,
+    latency: Res<Latency>,
+    server_clock: Res<ServerClock>,
+    reconnect_state: Res<ReconnectState>,
+    server_address: Res<ServerAddress>,
+    mut query: Query<&mut Text, With<ConnectionText>>,
+) {
+    let (value, color) = match *status {
+        ConnectionStatus::Connecting => (
+            format!("Connecting to {}...", server_address.input),
+            Color::YELLOW,
+        ),
+        ConnectionStatus::Connected => match latency.rtt {
+            Some(rtt) => (
+                format!(
+                    "Online: {} ms (+/- {} ms), {:.0}% loss",
+                    rtt.as_millis(),
+                    latency.jitter.as_millis(),
+                    server_clock.ticks.packet_loss() * 100.0
+                ),
+                Color::GREEN,
+            ),
+            None => ("Online".to_string(), Color::GREEN),
+        },
+        ConnectionStatus::Disconnected => {
+            let remaining = reconnect_state
+                .timer
+                .as_ref()
+                .map(|timer| timer.remaining_secs().ceil() as u32)
+                .unwrap_or_default();
+            (
+                format!("Disconnected, reconnecting in {}s", remaining),
+                Color::RED,
+            )
+        }
+        ConnectionStatus::Offline => ("Offline mode".to_string(), Color::GRAY),
+    };
+
+    for mut text in query.iter_mut() {
+        // Only touch the text when it changed, so it is not laid out again every frame
+        let section = &text.sections[0];
+        if section.value != value || section.style.color != color {
+            text.sections[0].value = value.clone();
+            text.sections[0].style.color = color;
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use bevy::asset::AssetServer;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::time::Duration;
 
-use crate::multiplayer_system::highscore::HighscoreInfoEvent;
-/// This component is used to store the current highscore value.
-#[derive(Component)]
+use crate::asset_system::levels::CurrentLevel;
+use crate::multiplayer_system::highscore::{HighscoreInfoEvent, HighscoreRemovedEvent};
+use crate::multiplayer_system::room_screen::RoomJoinedEvent;
+use crate::score_system::time::format_time;
+/// This component is used to store the highscores received from the server.
+///
+/// The highscores are stored per level, keyed by the LDtk level iid. Only the highscore
+/// of the level currently played is displayed.
+#[derive(Component, Default)]
 pub struct HighscoreText {
-    pub value: u64, //same datatype as seconds in Stopwatch::duration
+    pub values: HashMap<String, Duration>, //same datatype as Stopwatch::elapsed
 }
 /// Sets up the initial state of the highscore display
 ///
@@ -40,29 +48,59 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             left: Val::Px(20.0),
             ..default()
         }),
-        HighscoreText { value: 0 },
+        HighscoreText::default(),
     ));
 }
 /// Updates the highscore display
 ///
 /// This function is responsible for updating the highscore display in the ECS.
-/// It listens for `HighscoreInfoEvent` events, and when one is received, it updates the `HighscoreText` component
-/// and the `Text` component of the highscore display entity to reflect the new highscore.
+/// It listens for `HighscoreInfoEvent` events, and when one is received, it stores the new highscore
+/// for its level in the `HighscoreText` component. The `Text` component of the highscore display entity
+/// is then updated to show the highscore of the level currently played. Entering a room forgets all
+/// highscores, as the server sends the highscores of the new room right after. Highscores removed by the
+/// server operator are forgotten as well.
 ///
 /// # Arguments
 ///
 /// * `events` - An `EventReader` for `HighscoreInfoEvent` events.
+/// * `removed_events` - An `EventReader` for `HighscoreRemovedEvent` events.
+/// * `room_events` - An `EventReader` for `RoomJoinedEvent` events.
+/// * `current_level` - A reference to the `CurrentLevel` resource, which stores the iid of the level currently played.
 /// * `query` - A `Query` that fetches the `Text` and `HighscoreText` components of the highscore display entity.
 pub fn update_highscore(
     mut events: EventReader<HighscoreInfoEvent>,
+    mut removed_events: EventReader<HighscoreRemovedEvent>,
+    mut room_events: EventReader<RoomJoinedEvent>,
+    current_level: Res<CurrentLevel>,
     mut query: Query<(&mut Text, &mut HighscoreText), With<HighscoreText>>,
 ) {
-    for ev in events.read() {
-        let (mut text, mut highscore_text) = query.single_mut();
+    if events.is_empty()
+        && removed_events.is_empty()
+        && room_events.is_empty()
+        && !current_level.is_changed()
+    {
+        return;
+    }
 
-        if ev.0.time_in_seconds != 0 {
-            highscore_text.value = ev.0.time_in_seconds;
-            text.sections[0].value = format!("Highscore: {}", highscore_text.value);
-        }
+    let (mut text, mut highscore_text) = query.single_mut();
+    if room_events.read().count() > 0 {
+        highscore_text.values.clear();
     }
+    for ev in events.read() {
+        highscore_text
+            .values
+            .insert(ev.0.level_iid.clone(), ev.0.time);
+    }
+    for ev in removed_events.read() {
+        highscore_text.values.remove(&ev.0);
+    }
+
+    let current_highscore = current_level
+        .iid
+        .as_ref()
+        .and_then(|iid| highscore_text.values.get(iid));
+    text.sections[0].value = match current_highscore {
+        Some(value) => format!("Highscore: {}", format_time(*value)),
+        None => "No highscore yet!".to_string(),
+    };
 }
@@ -0,0 +1,150 @@
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use shared::LeaderboardEntry;
+
+use crate::multiplayer_system::leaderboard::LeaderboardInfoEvent;
+use crate::score_system::time::format_time;
+
+/// Key used to open and close the leaderboard screen.
+const TOGGLE_BUTTON: KeyCode = KeyCode::Tab;
+
+/// This component marks the root node of the leaderboard screen.
+#[derive(Component)]
+pub struct LeaderboardScreen;
+
+/// This component marks the text listing the leaderboard entries.
+#[derive(Component)]
+pub struct LeaderboardText;
+
+/// Bevy event to be fired when the player opens the leaderboard screen.
+#[derive(Event)]
+pub struct LeaderboardOpenedEvent;
+
+/// Sets up the leaderboard screen
+///
+/// This function is responsible for spawning the entities of the leaderboard screen in the ECS.
+/// It spawns a hidden `NodeBundle` entity with a `LeaderboardScreen` component that covers the center of the window,
+/// and a `TextBundle` child with a `LeaderboardText` component that lists the entries.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+///
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(15.0),
+                    left: Val::Percent(20.0),
+                    width: Val::Percent(60.0),
+                    padding: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            LeaderboardScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Loading leaderboard...",
+                    TextStyle {
+                        font: asset_server.load("fonts/Pixelfont.ttf"),
+                        font_size: 30.0,
+                        ..default()
+                    },
+                ),
+                LeaderboardText,
+            ));
+        });
+}
+
+/// Opens and closes the leaderboard screen
+///
+/// This function toggles the visibility of the leaderboard screen when the toggle key is pressed.
+/// Each time the screen is opened a `LeaderboardOpenedEvent` is sent, so the latest leaderboard is requested from the server.
+///
+/// # Arguments
+///
+/// * `keyboard_input` - The state of the keyboard input.
+/// * `query` - A `Query` that fetches the `Visibility` component of the leaderboard screen.
+/// * `opened_events` - An `EventWriter` for `LeaderboardOpenedEvent` events.
+pub fn toggle_leaderboard(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut query: Query<&mut Visibility, With<LeaderboardScreen>>,
+    mut opened_events: EventWriter<LeaderboardOpenedEvent>,
+) {
+    if !keyboard_input.just_pressed(TOGGLE_BUTTON) {
+        return;
+    }
+
+    for mut visibility in query.iter_mut() {
+        if *visibility == Visibility::Hidden {
+            *visibility = Visibility::Visible;
+            opened_events.send(LeaderboardOpenedEvent);
+        } else {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+/// Updates the leaderboard screen
+///
+/// This function listens for `LeaderboardInfoEvent` events, and when one is received,
+/// it updates the `Text` component of the leaderboard screen to list the received entries.
+///
+/// # Arguments
+///
+/// * `events` - An `EventReader` for `LeaderboardInfoEvent` events.
+/// * `query` - A `Query` that fetches the `Text` component of the leaderboard text entity.
+pub fn update_leaderboard(
+    mut events: EventReader<LeaderboardInfoEvent>,
+    mut query: Query<&mut Text, With<LeaderboardText>>,
+) {
+    for ev in events.read() {
+        let mut text = query.single_mut();
+
+        let mut lines = vec!["Leaderboard".to_string()];
+        if ev.0.entries.is_empty() {
+            lines.push("No finishes yet!".to_string());
+        }
+        for (index, entry) in ev.0.entries.iter().enumerate() {
+            lines.push(format_entry(index + 1, entry));
+        }
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+/// Formats a leaderboard entry as one line, e.g. `1. Speedy  00:42.180  2024-05-01`.
+fn format_entry(rank: usize, entry: &LeaderboardEntry) -> String {
+    format!(
+        "{}. {}  {}  {}",
+        rank,
+        entry.player_name,
+        format_time(entry.time),
+        format_date(entry.achieved_at)
+    )
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD` (UTC).
+///
+/// Uses the days-to-civil algorithm by Howard Hinnant, so no date library is needed.
+fn format_date(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
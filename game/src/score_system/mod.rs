@@ -1,2 +1,5 @@
+pub mod connection_label;
 pub mod highscore_label;
+pub mod leaderboard;
+pub mod race_label;
 pub mod time;
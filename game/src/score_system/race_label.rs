@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use bevy::asset::AssetServer;
+use bevy::prelude::*;
+use bevy::time::Real;
+
+use crate::multiplayer_system::race::RaceState;
+use crate::multiplayer_system::roster::Roster;
+use crate::score_system::time::format_time;
+
+/// How long "GO!" is shown after the start of a race.
+const GO_DISPLAY_DURATION: Duration = Duration::from_secs(1);
+
+/// This component marks the text showing the countdown and the results of a race.
+#[derive(Component)]
+pub struct RaceText;
+/// Sets up the initial state of the race display
+///
+/// This function is responsible for spawning the initial entities in the ECS for the race display.
+/// It spawns an empty `TextBundle` entity in the upper center of the window with a `RaceText` component to find it again.
+///
+/// # Arguments
+///
+/// * `commands` - A mutable reference to the `Commands` struct, which is used to spawn entities and insert components in the ECS.
+/// * `asset_server` - A reference to the `AssetServer`, which is used to load assets.
+///
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Pixelfont.ttf"),
+                font_size: 40.0,
+                ..default()
+            },
+        )
+        .with_text_alignment(TextAlignment::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(120.0),
+            left: Val::Percent(35.0),
+            ..default()
+        }),
+        RaceText,
+    ));
+}
+/// Updates the race display
+///
+/// This function shows the seconds until the start while the countdown of a race runs and "GO!" right after the start.
+/// Otherwise it shows how many players of the room are ready for the next race, or the finishing order of the last one.
+///
+/// # Arguments
+///
+/// * `time` - The real time of the game, which the start of the race is measured in.
+/// * `state` - The `RaceState` resource.
+/// * `roster` - The `Roster` resource with all players in the room.
+/// * `query` - A `Query` that fetches the `Text` component of the race display entity.
+pub fn update_race_label(
+    time: Res<Time<Real>>,
+    state: Res<RaceState>,
+    roster: Res<Roster>,
+    mut query: Query<&mut Text, With<RaceText>>,
+) {
+    let now = time.elapsed();
+    let value = if let Some(start_at) = state.start_at {
+        format!("{}", start_at.saturating_sub(now).as_secs_f32().ceil() as u32)
+    } else if state
+        .started_at
+        .is_some_and(|started_at| now < started_at + GO_DISPLAY_DURATION)
+    {
+        "GO!".to_string()
+    } else if state.ready || !state.ready_players.is_empty() {
+        format!(
+            "{}/{} ready - press R to {}",
+            state.ready_players.len(),
+            roster.len().max(1),
+            if state.ready { "cancel" } else { "race" }
+        )
+    } else if !state.results.is_empty() {
+        let placings: Vec<String> = state
+            .results
+            .iter()
+            .enumerate()
+            .map(|(place, placing)| {
+                format!("{}. {}  {}", place + 1, placing.player_name, format_time(placing.time))
+            })
+            .collect();
+        format!("Race results\n{}", placings.join("\n"))
+    } else {
+        String::new()
+    };
+
+    for mut text in query.iter_mut() {
+        // Only touch the text when it changed, so it is not laid out again every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
 use bevy::time::Stopwatch;
+
+use crate::multiplayer_system::highscore::RunTimeConfirmedEvent;
 /// TimeText component
 ///
 /// This component is used to store a `Stopwatch` that keeps track of the elapsed time.
@@ -7,11 +11,22 @@ use bevy::time::Stopwatch;
 pub struct TimeText {
     pub time: Stopwatch,
 }
+/// LastRunText component
+///
+/// This component marks the text showing the official time of the last finished run, as measured by the server.
+#[derive(Component)]
+pub struct LastRunText;
+/// Bevy event to be fired when a new run starts
+///
+/// Sent whenever the stopwatch is reset, so the server can start measuring the run as well.
+#[derive(Event)]
+pub struct RunStartedEvent;
 /// Sets up the initial state of the time display
 ///
 /// This function is responsible for spawning the initial entities in the ECS for the time display.
 /// It spawns a `TextBundle` entity with a `Text` component that displays the current elapsed time,
 /// and a `TimeText` component that stores a `Stopwatch` to keep track of the elapsed time.
+/// Below it a second `TextBundle` entity with a `LastRunText` component shows the time of the last run.
 ///
 /// # Arguments
 ///
@@ -24,7 +39,7 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         // Create a TextBundle that has a Text with a single section.
         TextBundle::from_section(
             // Accepts a `String` or any type that converts into a `String`, such as `&str`
-            "0",
+            format_time(Duration::ZERO),
             TextStyle {
                 // This font is loaded and will be used instead of the default font.
                 font: asset_server.load("fonts/Pixelfont.ttf"),
@@ -44,13 +59,31 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             time: Stopwatch::new(),
         },
     ));
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Pixelfont.ttf"),
+                font_size: 25.0,
+                ..default()
+            },
+        )
+        .with_text_alignment(TextAlignment::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            right: Val::Px(20.0),
+            ..default()
+        }),
+        LastRunText,
+    ));
 }
 /// Updates the time display
 ///
 /// This function is responsible for updating the time display in the ECS.
 /// It fetches the `TimeText` and `Text` components of the time display entity,
 /// updates the `Stopwatch` in the `TimeText` component to reflect the elapsed time,
-/// and updates the `Text` component to display the new elapsed time.
+/// and updates the `Text` component to display the new elapsed time formatted by `format_time`.
 ///
 /// # Arguments
 ///
@@ -62,6 +95,41 @@ pub fn change_time_text(
 ) {
     for (mut time_text, mut text) in query.iter_mut() {
         time_text.time.tick(time.delta());
-        text.sections[0].value = format!("{:?}", time_text.time.elapsed().as_secs());
+        text.sections[0].value = format_time(time_text.time.elapsed());
+    }
+}
+/// Updates the last run display
+///
+/// This function listens for `RunTimeConfirmedEvent` events, and when one is received,
+/// it updates the `Text` component of the last run display to the official time sent by the server.
+///
+/// # Arguments
+///
+/// * `events` - An `EventReader` for `RunTimeConfirmedEvent` events.
+/// * `query` - A `Query` that fetches the `Text` component of the last run display entity.
+pub fn update_last_run(
+    mut events: EventReader<RunTimeConfirmedEvent>,
+    mut query: Query<&mut Text, With<LastRunText>>,
+) {
+    for ev in events.read() {
+        let mut text = query.single_mut();
+        text.sections[0].value = format!("Last run: {}", format_time(ev.0.time));
     }
 }
+/// Formats a duration as `mm:ss.mmm`
+///
+/// Used to display run times with millisecond precision, so runs like 41.2s and 41.9s can be told apart.
+/// Runs longer than an hour keep counting the minutes, e.g. `75:03.120`.
+///
+/// # Arguments
+///
+/// * `duration` - The duration to format.
+pub fn format_time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{:02}:{:02}.{:03}",
+        millis / 60_000,
+        (millis / 1_000) % 60,
+        millis % 1_000
+    )
+}
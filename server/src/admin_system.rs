@@ -0,0 +1,447 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use serde::{Deserialize, Serialize};
+use shared::ServerMessage;
+
+use crate::players_system::{Player, Session, Spectator};
+use crate::shutdown_system::ShutdownEvent;
+use crate::storage;
+
+/// Shown for the `help` command.
+const HELP: &str = "\
+Commands:
+  players                         List the connected players and spectators
+  kick <id|name>                  Disconnect a player or spectator
+  ban <id|name>                   Disconnect a player or spectator and ban the name and session
+  ban-session <token>             Ban a session token
+  unban <name|token>              Lift a ban
+  highscore reset <level_iid>     Remove the highscore of a level in the lobby
+  highscore set <level_iid> <s>   Set the highscore of a level in the lobby to the given seconds
+  say <text>                      Send a message to all players
+  shutdown [reason]               Disconnect all players and stop the server";
+
+//
+// ------> Resources <------ //
+//
+
+/// Lines typed into the console of the server. They are read on a separate thread, as reading from
+/// stdin blocks until a line is complete.
+#[derive(Resource)]
+pub struct AdminConsole(Mutex<Receiver<String>>);
+
+impl AdminConsole {
+    /// Starts reading lines from stdin. The thread ends when stdin is closed, e.g. when the server
+    /// runs without a terminal.
+    pub fn from_stdin() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        AdminConsole(Mutex::new(receiver))
+    }
+}
+
+/// Names and session tokens which may not join the game. Names are compared case insensitive, see [`normalize_name`].
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct Bans {
+    names: HashSet<String>,
+    sessions: HashSet<String>,
+}
+
+impl Bans {
+    /// Returns `true` if the name or the session token of a join request is banned.
+    pub fn is_banned(&self, name: &str, session_token: Option<&str>) -> bool {
+        self.names.contains(&normalize_name(name))
+            || session_token.is_some_and(|token| self.sessions.contains(token))
+    }
+
+    fn ban_name(&mut self, name: &str) {
+        self.names.insert(normalize_name(name));
+    }
+
+    fn ban_session(&mut self, token: &str) {
+        self.sessions.insert(token.to_string());
+    }
+
+    /// Lifts the ban of a name or session token. Returns `false` if neither was banned.
+    fn unban(&mut self, name_or_token: &str) -> bool {
+        let name_unbanned = self.names.remove(&normalize_name(name_or_token));
+        let session_unbanned = self.sessions.remove(name_or_token);
+        name_unbanned || session_unbanned
+    }
+}
+
+/// Location of the file the [`Bans`] are persisted to, see [`ServerConfig::bans_file`](crate::config::ServerConfig).
+#[derive(Resource)]
+pub struct BanStorage {
+    pub path: PathBuf,
+}
+
+//
+// ------> Events <------ //
+//
+
+/// A command typed into the console of the server, see [`parse_admin_command`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Help,
+    Players,
+    Kick(String),
+    Ban(String),
+    BanSession(String),
+    Unban(String),
+    ResetHighscore(String),
+    SetHighscore { level_iid: String, time: Duration },
+    Broadcast(String),
+    /// Stops the server, optionally with a reason shown to the players.
+    Shutdown(Option<String>),
+}
+
+/// Called when the server operator typed a valid command.
+#[derive(Event)]
+pub struct AdminCommandEvent(pub AdminCommand);
+
+//
+// ------> Systems <------ //
+//
+
+/// Loads the persisted bans from the [`BanStorage`] file at startup.
+pub fn load_bans(storage: Res<BanStorage>, mut bans: ResMut<Bans>) {
+    match storage::load::<Bans>(&storage.path) {
+        Ok(Some(stored)) => *bans = stored,
+        Ok(None) => {}
+        Err(error) => error!(path = %storage.path.display(), %error, "Failed to load bans."),
+    }
+}
+
+/// Parses the lines typed into the [`AdminConsole`] since the last frame and fires an [`AdminCommandEvent`]
+/// for each valid command. Invalid commands are answered on the console.
+pub fn read_admin_console(console: Res<AdminConsole>, mut events: EventWriter<AdminCommandEvent>) {
+    let Ok(receiver) = console.0.lock() else {
+        return;
+    };
+    while let Ok(line) = receiver.try_recv() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_admin_command(&line) {
+            Ok(command) => events.send(AdminCommandEvent(command)),
+            Err(reason) => println!("{} Type `help` for a list of commands.", reason),
+        }
+    }
+}
+
+/// Executes the commands managing the connected players and the server itself. Kicked and banned players
+/// get a [`ServerMessage::Kicked`] message before they are disconnected, so they do not reconnect on their own.
+/// Spectators are kicked and banned the same way, by their client id or the name they sent.
+/// Highscore commands are handled by [`crate::highscore_system::on_admin_highscore_command`], the shutdown by
+/// [`crate::shutdown_system::on_shutdown`]. Kicks, bans and announcements are logged, answers to queries
+/// like `players` are printed on the console only.
+pub fn on_admin_command(
+    mut events: EventReader<AdminCommandEvent>,
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    mut bans: ResMut<Bans>,
+    ban_storage: Res<BanStorage>,
+    players: Query<(Entity, &Player, &Session)>,
+    spectators: Query<(Entity, &Spectator)>,
+    mut shutdown_events: EventWriter<ShutdownEvent>,
+) {
+    // Each command needs a different part of the server.
+    #![allow(clippy::too_many_arguments)]
+    for ev in events.read() {
+        match &ev.0 {
+            AdminCommand::Help => println!("{}", HELP),
+            AdminCommand::Players => {
+                println!(
+                    "{} player(s) and {} spectator(s) connected.",
+                    players.iter().count(),
+                    spectators.iter().count()
+                );
+                for (_, player, session) in players.iter() {
+                    println!(
+                        "  {} (id {}, client {}) in room {}, session {}",
+                        player.name, player.id, player.client_id, player.room_id, session.token
+                    );
+                }
+                for (_, spectator) in spectators.iter() {
+                    println!(
+                        "  spectator {} (client {}) in room {}",
+                        spectator.name, spectator.client_id, spectator.room_id
+                    );
+                }
+            }
+            AdminCommand::Kick(target) | AdminCommand::Ban(target) => {
+                let is_ban = matches!(ev.0, AdminCommand::Ban(_));
+                // Players are looked up first, spectators only if no player matches
+                let client = players
+                    .iter()
+                    .find(|(_, player, _)| matches_player(player, target))
+                    .map(|(entity, player, session)| KickTarget {
+                        entity,
+                        client_id: player.client_id,
+                        name: &player.name,
+                        session_token: Some(&session.token),
+                    })
+                    .or_else(|| {
+                        spectators
+                            .iter()
+                            .find(|(_, spectator)| matches_spectator(spectator, target))
+                            .map(|(entity, spectator)| KickTarget {
+                                entity,
+                                client_id: spectator.client_id,
+                                name: &spectator.name,
+                                session_token: spectator.session_token.as_deref(),
+                            })
+                    });
+
+                if is_ban {
+                    let name = client.as_ref().map_or(target.as_str(), |client| client.name);
+                    bans.ban_name(name);
+                    if let Some(token) = client.as_ref().and_then(|client| client.session_token) {
+                        bans.ban_session(token);
+                    }
+                    save_bans(&ban_storage, &bans);
+                    info!(%name, "Banned player.");
+                }
+
+                let Some(client) = client else {
+                    if !is_ban {
+                        println!("There is no player {}.", target);
+                    }
+                    continue;
+                };
+                let reason = if is_ban {
+                    "You were banned from this server."
+                } else {
+                    "You were kicked from this server."
+                };
+                info!(name = %client.name, client_id = client.client_id, "Kicked player.");
+                kick(&mut server, client.client_id, reason);
+                commands.entity(client.entity).despawn();
+            }
+            AdminCommand::BanSession(token) => {
+                bans.ban_session(token);
+                save_bans(&ban_storage, &bans);
+                info!(session = %token, "Banned session.");
+
+                for (entity, player, session) in players.iter() {
+                    if &session.token == token {
+                        info!(name = %player.name, player_id = player.id, "Kicked player.");
+                        kick(&mut server, player.client_id, "You were banned from this server.");
+                        commands.entity(entity).despawn();
+                    }
+                }
+                for (entity, spectator) in spectators.iter() {
+                    if spectator.session_token.as_ref() == Some(token) {
+                        info!(name = %spectator.name, client_id = spectator.client_id, "Kicked spectator.");
+                        kick(&mut server, spectator.client_id, "You were banned from this server.");
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
+            AdminCommand::Unban(name_or_token) => {
+                if bans.unban(name_or_token) {
+                    save_bans(&ban_storage, &bans);
+                    info!(%name_or_token, "Lifted ban.");
+                } else {
+                    println!("{} is not banned.", name_or_token);
+                }
+            }
+            AdminCommand::Broadcast(text) => {
+                info!(%text, "Announced message.");
+                server
+                    .endpoint()
+                    .try_broadcast_message(ServerMessage::Announcement(text.clone()));
+            }
+            AdminCommand::Shutdown(reason) => {
+                shutdown_events.send(ShutdownEvent {
+                    reason: reason
+                        .clone()
+                        .unwrap_or_else(|| "The server is shutting down.".to_string()),
+                });
+            }
+            AdminCommand::ResetHighscore(_) | AdminCommand::SetHighscore { .. } => {}
+        }
+    }
+}
+
+/// Parses a line typed into the console of the server. Returns the reason if the line is no valid command.
+pub fn parse_admin_command(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let argument = |usage: &str| {
+        if rest.is_empty() {
+            Err(format!("Usage: {}.", usage))
+        } else {
+            Ok(rest.to_string())
+        }
+    };
+
+    match command {
+        "help" => Ok(AdminCommand::Help),
+        "players" => Ok(AdminCommand::Players),
+        "kick" => argument("kick <id|name>").map(AdminCommand::Kick),
+        "ban" => argument("ban <id|name>").map(AdminCommand::Ban),
+        "ban-session" => argument("ban-session <token>").map(AdminCommand::BanSession),
+        "unban" => argument("unban <name|token>").map(AdminCommand::Unban),
+        "say" => argument("say <text>").map(AdminCommand::Broadcast),
+        "shutdown" => Ok(AdminCommand::Shutdown((!rest.is_empty()).then(|| rest.to_string()))),
+        "highscore" => {
+            let arguments: Vec<&str> = rest.split_whitespace().collect();
+            match arguments.as_slice() {
+                ["reset", level_iid] => Ok(AdminCommand::ResetHighscore(level_iid.to_string())),
+                ["set", level_iid, seconds] => match seconds.parse::<f64>() {
+                    Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                        Ok(AdminCommand::SetHighscore {
+                            level_iid: level_iid.to_string(),
+                            time: Duration::from_secs_f64(seconds),
+                        })
+                    }
+                    _ => Err(format!("`{}` is no valid time in seconds.", seconds)),
+                },
+                _ => Err("Usage: highscore reset <level_iid> or highscore set <level_iid> <seconds>.".to_string()),
+            }
+        }
+        _ => Err(format!("Unknown command `{}`.", command)),
+    }
+}
+
+/// A connected player or spectator the server operator kicks or bans.
+struct KickTarget<'a> {
+    entity: Entity,
+    client_id: u64,
+    name: &'a str,
+    session_token: Option<&'a str>,
+}
+
+/// Returns `true` if the target typed by the server operator is the id or the name of the player.
+fn matches_player(player: &Player, target: &str) -> bool {
+    target.parse::<u64>().is_ok_and(|id| id == player.id)
+        || normalize_name(&player.name) == normalize_name(target)
+}
+
+/// Returns `true` if the target typed by the server operator is the client id or the name of the spectator.
+fn matches_spectator(spectator: &Spectator, target: &str) -> bool {
+    target.parse::<u64>().is_ok_and(|id| id == spectator.client_id)
+        || normalize_name(&spectator.name) == normalize_name(target)
+}
+
+/// Form in which player names are compared by the server operator and in the [`Bans`], so a name matches
+/// regardless of case and surrounding whitespace.
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Tells the client why it is disconnected and closes its connection.
+fn kick(server: &mut Server, client_id: u64, reason: &str) {
+    let endpoint = server.endpoint_mut();
+    endpoint.try_send_message(client_id, ServerMessage::Kicked(reason.to_string()));
+    endpoint.try_disconnect_client(client_id);
+}
+
+/// Saves the bans to the [`BanStorage`] file. Returns `false` if that failed.
+pub fn save_bans(storage: &BanStorage, bans: &Bans) -> bool {
+    if let Err(error) = storage::save_atomic(&storage.path, bans) {
+        error!(path = %storage.path.display(), %error, "Failed to save bans.");
+        return false;
+    }
+    true
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_parse_admin_commands() {
+    assert_eq!(parse_admin_command(" players "), Ok(AdminCommand::Players));
+    assert_eq!(
+        parse_admin_command("kick Speedy Gonzales"),
+        Ok(AdminCommand::Kick("Speedy Gonzales".to_string()))
+    );
+    assert_eq!(
+        parse_admin_command("say  Restart in 5 minutes"),
+        Ok(AdminCommand::Broadcast("Restart in 5 minutes".to_string()))
+    );
+    assert_eq!(
+        parse_admin_command("highscore set abc 41.5"),
+        Ok(AdminCommand::SetHighscore {
+            level_iid: "abc".to_string(),
+            time: Duration::from_millis(41500),
+        })
+    );
+    assert_eq!(parse_admin_command("shutdown"), Ok(AdminCommand::Shutdown(None)));
+    assert_eq!(
+        parse_admin_command("shutdown Update to 1.2"),
+        Ok(AdminCommand::Shutdown(Some("Update to 1.2".to_string())))
+    );
+    assert!(parse_admin_command("kick").is_err());
+    assert!(parse_admin_command("highscore set abc -1").is_err());
+    assert!(parse_admin_command("highscore clear abc").is_err());
+    assert!(parse_admin_command("reboot").is_err());
+}
+
+#[test]
+fn test_bans_match_names_and_sessions() {
+    let mut bans = Bans::default();
+    bans.ban_name("Speedy");
+    bans.ban_session("0123");
+
+    assert!(bans.is_banned(" speedy ", None));
+    assert!(bans.is_banned("Other", Some("0123")));
+    assert!(!bans.is_banned("Other", Some("4567")));
+
+    assert!(bans.unban("SPEEDY"));
+    assert!(!bans.unban("SPEEDY"));
+    assert!(!bans.is_banned("Speedy", None));
+}
+
+#[test]
+fn test_commands_and_bans_match_names_alike() {
+    let player = Player {
+        client_id: 1,
+        id: 1,
+        name: "Äpfel".to_string(),
+        room_id: 0,
+    };
+    let mut bans = Bans::default();
+    bans.ban_name(" äPFEL");
+
+    assert!(matches_player(&player, "ÄPFEL "));
+    assert!(bans.is_banned(&player.name, None));
+}
+
+#[test]
+fn test_commands_match_spectators_by_client_id_and_name() {
+    let spectator = Spectator {
+        client_id: 5,
+        name: "Watcher".to_string(),
+        session_token: None,
+        room_id: 0,
+    };
+
+    assert!(matches_spectator(&spectator, "5"));
+    assert!(matches_spectator(&spectator, " watcher"));
+    assert!(!matches_spectator(&spectator, "6"));
+}
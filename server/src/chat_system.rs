@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use shared::{ChatMessage, ServerMessage};
+
+use crate::players_system::Player;
+use crate::rooms_system::RoomMembers;
+
+/// Maximum number of characters of a chat message.
+const MAX_CHAT_LENGTH: usize = 200;
+
+/// Number of messages a player can send in a burst before being rate limited.
+const CHAT_BURST: f32 = 5.0;
+
+/// Time after which a player can send one more message.
+const CHAT_REFILL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Words replaced by asterisks in chat messages. Compared case insensitive with every word of a message.
+const FILTERED_WORDS: [&str; 8] = [
+    "idiot", "stupid", "moron", "dumb", "loser", "noob", "shit", "fuck",
+];
+
+//
+// ------> Components <------ //
+//
+
+/// Limits how many chat messages a player can send. Each message takes a token, tokens come back
+/// over time up to [`CHAT_BURST`], so short bursts are fine but flooding the chat is not.
+#[derive(Component)]
+pub struct ChatLimiter {
+    tokens: f32,
+    last_refill: Duration,
+}
+
+impl ChatLimiter {
+    pub fn new(now: Duration) -> Self {
+        ChatLimiter {
+            tokens: CHAT_BURST,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token for a message. Returns `false` if the player has to wait before sending the next one.
+    fn try_take(&mut self, now: Duration) -> bool {
+        let refilled = (now - self.last_refill).as_secs_f32() / CHAT_REFILL_INTERVAL.as_secs_f32();
+        self.tokens = (self.tokens + refilled).min(CHAT_BURST);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+//
+// ------> Events <------ //
+//
+
+/// Called when a player sent a chat message.
+#[derive(Event)]
+pub struct ChatEvent {
+    pub client_id: u64,
+    pub text: String,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Forwards the chat messages of the players to everybody in the same room as [`ServerMessage::ChatBroadcast`].
+/// Messages are checked by [`validate_chat_message`], filtered by [`filter_words`] and rate limited by the
+/// [`ChatLimiter`] of the player. Rejected messages are answered with a [`ServerMessage::ChatRejected`] message.
+pub fn on_chat_message(
+    time: Res<Time>,
+    mut events: EventReader<ChatEvent>,
+    server: Res<Server>,
+    members: RoomMembers,
+    mut limiters: Query<(&Player, &mut ChatLimiter)>,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        let Some((player, mut limiter)) = limiters
+            .iter_mut()
+            .find(|(player, _)| player.client_id == ev.client_id)
+        else {
+            // Only players who joined the game with a name can chat.
+            continue;
+        };
+
+        let text = match validate_chat_message(&ev.text) {
+            Ok(text) if limiter.try_take(time.elapsed()) => Ok(filter_words(&text)),
+            Ok(_) => Err("You are sending messages too fast, wait a moment.".to_string()),
+            Err(reason) => Err(reason),
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(reason) => {
+                server
+                    .endpoint()
+                    .try_send_message(ev.client_id, ServerMessage::ChatRejected(reason));
+                continue;
+            }
+        };
+
+        info!(name = %player.name, room_id = player.room_id, %text, "Chat message.");
+        members.send_to_room(
+            &server,
+            player.room_id,
+            ServerMessage::ChatBroadcast(ChatMessage {
+                player_id: player.id,
+                player_name: player.name.clone(),
+                text,
+            }),
+        );
+    }
+}
+
+/// Removes control characters and surrounding whitespace from a chat message and checks that it is neither
+/// empty nor longer than [`MAX_CHAT_LENGTH`] characters. Returns the cleaned message or the reason why it is invalid.
+pub fn validate_chat_message(text: &str) -> Result<String, String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err("The message is empty.".to_string());
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(format!(
+            "The message must not have more than {} characters.",
+            MAX_CHAT_LENGTH
+        ));
+    }
+    Ok(text.to_string())
+}
+
+/// Replaces the letters of every word in [`FILTERED_WORDS`] with asterisks. Punctuation around a word is kept.
+pub fn filter_words(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let core = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if FILTERED_WORDS.contains(&core.to_lowercase().as_str()) {
+                word.replace(core, &"*".repeat(core.chars().count()))
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_chat_message_is_validated_and_filtered() {
+    assert_eq!(validate_chat_message("  gg\n "), Ok("gg".to_string()));
+    assert!(validate_chat_message(" \t ").is_err());
+    assert!(validate_chat_message(&"a".repeat(MAX_CHAT_LENGTH + 1)).is_err());
+
+    assert_eq!(filter_words("You NOOB, nice jump!"), "You ****, nice jump!");
+    assert_eq!(filter_words("snoob is fine"), "snoob is fine");
+}
+
+#[test]
+fn test_chat_limiter_allows_bursts_but_not_flooding() {
+    let mut limiter = ChatLimiter::new(Duration::ZERO);
+    let sent = (0..10)
+        .filter(|_| limiter.try_take(Duration::from_millis(100)))
+        .count();
+    assert_eq!(sent, CHAT_BURST as usize);
+
+    assert!(limiter.try_take(Duration::from_millis(100) + CHAT_REFILL_INTERVAL));
+    assert!(!limiter.try_take(Duration::from_millis(200) + CHAT_REFILL_INTERVAL));
+}
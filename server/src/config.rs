@@ -0,0 +1,436 @@
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use clap::Parser;
+use serde::Deserialize;
+use shared::logging::{LogConfig, LogFormat};
+
+/// Hostname used for the self signed certificate when none is configured.
+const DEFAULT_HOSTNAME: &str = "JumpNRun_Server";
+
+/// Address the server binds to when none is configured.
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Port the server listens on when none is configured.
+const DEFAULT_PORT: u16 = 8123;
+
+/// Interval in milliseconds between two movement updates sent to the clients when none is configured.
+const DEFAULT_UPDATE_INTERVAL_MS: u64 = 20;
+
+/// Seconds without a movement update after which a player is removed when none is configured.
+const DEFAULT_INACTIVE_TIMEOUT_SECS: u64 = 10;
+
+/// Path of the certificate file used when none is configured.
+const DEFAULT_CERT_FILE: &str = "data/cert.pem";
+
+/// Path of the private key file used when none is configured.
+const DEFAULT_KEY_FILE: &str = "data/key.pem";
+
+/// Distance in pixels within which players get the movements of each other with every update when none is configured.
+const DEFAULT_INTEREST_RADIUS: f32 = 1000.0;
+
+/// Path of the highscore file used when none is configured.
+const DEFAULT_HIGHSCORE_FILE: &str = "data/highscore.json";
+
+/// Path of the leaderboard file used when none is configured.
+const DEFAULT_LEADERBOARD_FILE: &str = "data/leaderboard.json";
+
+/// Number of finishes kept on the leaderboard of each level when none is configured.
+const DEFAULT_LEADERBOARD_SIZE: usize = 10;
+
+/// Path of the ban file used when none is configured.
+const DEFAULT_BANS_FILE: &str = "data/bans.json";
+
+/// Longest allowed interval between two movement updates. Ghost players stutter visibly above that.
+const MAX_UPDATE_INTERVAL_MS: u64 = 1000;
+
+/// Command line arguments of the server. Each option can also be set with the environment variable
+/// named in its help text. Options not given here are read from the config file.
+#[derive(Parser, Debug, Default)]
+#[command(about = "Server for the multiplayer mode of Jump 'n' Run")]
+pub struct Args {
+    /// Path of an optional TOML config file.
+    #[arg(long, env = "SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// IP address to bind to, e.g. `0.0.0.0` or `127.0.0.1`.
+    #[arg(long, env = "SERVER_BIND_ADDR")]
+    pub bind_addr: Option<String>,
+
+    /// Port to listen on.
+    #[arg(long, env = "SERVER_PORT")]
+    pub port: Option<u16>,
+
+    /// Hostname used for the self signed certificate.
+    #[arg(long, env = "SERVER_HOSTNAME")]
+    pub hostname: Option<String>,
+
+    /// Milliseconds between two movement updates sent to the clients.
+    #[arg(long, env = "SERVER_UPDATE_INTERVAL_MS")]
+    pub update_interval_ms: Option<u64>,
+
+    /// Seconds without a movement update after which a player is removed.
+    #[arg(long, env = "SERVER_INACTIVE_TIMEOUT_SECS")]
+    pub inactive_timeout_secs: Option<u64>,
+
+    /// Path of the certificate file. A self signed certificate is generated and saved there if it does not exist.
+    #[arg(long, env = "SERVER_CERT_FILE")]
+    pub cert_file: Option<PathBuf>,
+
+    /// Path of the private key file belonging to the certificate.
+    #[arg(long, env = "SERVER_KEY_FILE")]
+    pub key_file: Option<PathBuf>,
+
+    /// Distance in pixels within which players get the movements of each other with every update.
+    /// Players further away are updated less often.
+    #[arg(long, env = "SERVER_INTEREST_RADIUS")]
+    pub interest_radius: Option<f32>,
+
+    /// Seconds after which the server is expected to be back when it is stopped, told to the players
+    /// when shutting down. Leave it out if the server does not restart on its own.
+    #[arg(long, env = "SERVER_RESTART_ETA_SECS")]
+    pub restart_eta_secs: Option<u64>,
+
+    /// Address to serve `/metrics` and `/healthz` on over HTTP, e.g. `127.0.0.1:9100`.
+    /// Leave it out to not serve any metrics.
+    #[arg(long, env = "SERVER_METRICS_ADDR")]
+    pub metrics_addr: Option<String>,
+
+    /// Path of the file the highscores of the lobby are saved to.
+    #[arg(long, env = "SERVER_HIGHSCORE_FILE")]
+    pub highscore_file: Option<PathBuf>,
+
+    /// Path of the file the leaderboards are saved to.
+    #[arg(long, env = "SERVER_LEADERBOARD_FILE")]
+    pub leaderboard_file: Option<PathBuf>,
+
+    /// Number of finishes kept on the leaderboard of each level.
+    #[arg(long, env = "SERVER_LEADERBOARD_SIZE")]
+    pub leaderboard_size: Option<usize>,
+
+    /// Path of the file the banned names and sessions are saved to.
+    #[arg(long, env = "SERVER_BANS_FILE")]
+    pub bans_file: Option<PathBuf>,
+
+    /// Format of the log, `pretty` text or `json` lines. The level is set with `RUST_LOG`, e.g. `RUST_LOG=debug`.
+    #[arg(long, env = "SERVER_LOG_FORMAT")]
+    pub log_format: Option<String>,
+
+    /// Path of a file the log is appended to instead of writing it to stderr.
+    #[arg(long, env = "SERVER_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+}
+
+/// Content of the TOML config file. Every key is optional.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    bind_addr: Option<String>,
+    port: Option<u16>,
+    hostname: Option<String>,
+    update_interval_ms: Option<u64>,
+    inactive_timeout_secs: Option<u64>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    interest_radius: Option<f32>,
+    restart_eta_secs: Option<u64>,
+    metrics_addr: Option<String>,
+    highscore_file: Option<PathBuf>,
+    leaderboard_file: Option<PathBuf>,
+    leaderboard_size: Option<usize>,
+    bans_file: Option<PathBuf>,
+    log_format: Option<String>,
+    log_file: Option<PathBuf>,
+}
+
+/// Errors which make the server refuse to start.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    Read(PathBuf, io::Error),
+    /// The config file is no valid TOML or contains unknown keys.
+    Parse(PathBuf, toml::de::Error),
+    /// A value is out of its allowed range.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => {
+                write!(f, "failed to read config file {}: {}", path.display(), error)
+            }
+            ConfigError::Parse(path, error) => {
+                write!(f, "invalid config file {}: {}", path.display(), error)
+            }
+            ConfigError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Bevy resource holding the validated configuration of the server.
+#[derive(Resource, Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_addr: IpAddr,
+    pub port: u16,
+    pub hostname: String,
+    /// Interval between two movement updates sent to the clients.
+    pub update_interval: Duration,
+    /// Time without a movement update after which a player is removed.
+    pub inactive_timeout: Duration,
+    /// Certificate of the server. Kept across restarts so clients can pin its fingerprint.
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    /// Distance in pixels within which players get the movements of each other with every update.
+    pub interest_radius: f32,
+    /// Time after which the server is expected to be back after shutting down. `None` if unknown.
+    pub restart_eta: Option<Duration>,
+    /// Address of the HTTP endpoint serving the metrics and health of the server. `None` if disabled.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Files the highscores, leaderboards and bans are persisted to.
+    pub highscore_file: PathBuf,
+    pub leaderboard_file: PathBuf,
+    pub bans_file: PathBuf,
+    /// Number of finishes kept on the leaderboard of each level.
+    pub leaderboard_size: usize,
+    /// Format of the log events. The log is written to stderr if no file is configured.
+    pub log_format: LogFormat,
+    pub log_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addr: DEFAULT_BIND_ADDR,
+            port: DEFAULT_PORT,
+            hostname: DEFAULT_HOSTNAME.to_string(),
+            update_interval: Duration::from_millis(DEFAULT_UPDATE_INTERVAL_MS),
+            inactive_timeout: Duration::from_secs(DEFAULT_INACTIVE_TIMEOUT_SECS),
+            cert_file: PathBuf::from(DEFAULT_CERT_FILE),
+            key_file: PathBuf::from(DEFAULT_KEY_FILE),
+            interest_radius: DEFAULT_INTEREST_RADIUS,
+            restart_eta: None,
+            metrics_addr: None,
+            highscore_file: PathBuf::from(DEFAULT_HIGHSCORE_FILE),
+            leaderboard_file: PathBuf::from(DEFAULT_LEADERBOARD_FILE),
+            bans_file: PathBuf::from(DEFAULT_BANS_FILE),
+            leaderboard_size: DEFAULT_LEADERBOARD_SIZE,
+            log_format: LogFormat::default(),
+            log_file: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the configuration from the command line, the environment and the config file.
+    /// Command line arguments overwrite environment variables, which overwrite the config file.
+    pub fn load() -> Result<Self, ConfigError> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => read_config_file(path)?,
+            None => ConfigFile::default(),
+        };
+        Self::from_sources(args, file)
+    }
+
+    /// The socket address the server listens on.
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.port)
+    }
+
+    /// Where and how the log events are written, see [`shared::logging::init_logging`].
+    pub fn log_config(&self) -> LogConfig {
+        LogConfig {
+            format: self.log_format,
+            file: self.log_file.clone(),
+        }
+    }
+
+    /// Merges the arguments with the config file, falls back to the defaults and validates the result.
+    fn from_sources(args: Args, file: ConfigFile) -> Result<Self, ConfigError> {
+        let default = ServerConfig::default();
+
+        let bind_addr = match args.bind_addr.or(file.bind_addr) {
+            Some(bind_addr) => bind_addr.parse().map_err(|_| {
+                ConfigError::Invalid(format!("bind address `{}` is no valid IP address", bind_addr))
+            })?,
+            None => default.bind_addr,
+        };
+
+        let port = args.port.or(file.port).unwrap_or(default.port);
+        if port == 0 {
+            return Err(ConfigError::Invalid("port must not be 0".to_string()));
+        }
+
+        let hostname = args.hostname.or(file.hostname).unwrap_or(default.hostname);
+        if hostname.trim().is_empty() {
+            return Err(ConfigError::Invalid("hostname must not be empty".to_string()));
+        }
+
+        let update_interval_ms = args
+            .update_interval_ms
+            .or(file.update_interval_ms)
+            .unwrap_or(DEFAULT_UPDATE_INTERVAL_MS);
+        if !(1..=MAX_UPDATE_INTERVAL_MS).contains(&update_interval_ms) {
+            return Err(ConfigError::Invalid(format!(
+                "update interval must be between 1 and {} ms, got {} ms",
+                MAX_UPDATE_INTERVAL_MS, update_interval_ms
+            )));
+        }
+        let update_interval = Duration::from_millis(update_interval_ms);
+
+        let inactive_timeout = Duration::from_secs(
+            args.inactive_timeout_secs
+                .or(file.inactive_timeout_secs)
+                .unwrap_or(DEFAULT_INACTIVE_TIMEOUT_SECS),
+        );
+        if inactive_timeout <= update_interval {
+            return Err(ConfigError::Invalid(format!(
+                "inactive timeout of {} s must be longer than the update interval",
+                inactive_timeout.as_secs()
+            )));
+        }
+
+        let cert_file = args.cert_file.or(file.cert_file).unwrap_or(default.cert_file);
+        let key_file = args.key_file.or(file.key_file).unwrap_or(default.key_file);
+        if cert_file == key_file {
+            return Err(ConfigError::Invalid(
+                "certificate and key file must not be the same file".to_string(),
+            ));
+        }
+
+        let interest_radius = args
+            .interest_radius
+            .or(file.interest_radius)
+            .unwrap_or(default.interest_radius);
+        if !interest_radius.is_finite() || interest_radius <= 0.0 {
+            return Err(ConfigError::Invalid(format!(
+                "interest radius must be a positive number of pixels, got {}",
+                interest_radius
+            )));
+        }
+
+        let restart_eta = args
+            .restart_eta_secs
+            .or(file.restart_eta_secs)
+            .map(Duration::from_secs);
+
+        let metrics_addr = match args.metrics_addr.or(file.metrics_addr) {
+            Some(metrics_addr) => Some(metrics_addr.parse().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "metrics address `{}` is no valid socket address",
+                    metrics_addr
+                ))
+            })?),
+            None => None,
+        };
+
+        let highscore_file = args
+            .highscore_file
+            .or(file.highscore_file)
+            .unwrap_or(default.highscore_file);
+        let leaderboard_file = args
+            .leaderboard_file
+            .or(file.leaderboard_file)
+            .unwrap_or(default.leaderboard_file);
+        let bans_file = args.bans_file.or(file.bans_file).unwrap_or(default.bans_file);
+
+        let leaderboard_size = args
+            .leaderboard_size
+            .or(file.leaderboard_size)
+            .unwrap_or(default.leaderboard_size);
+        if leaderboard_size == 0 {
+            return Err(ConfigError::Invalid(
+                "leaderboard size must be at least 1".to_string(),
+            ));
+        }
+
+        let log_format = match args.log_format.or(file.log_format) {
+            Some(log_format) => log_format.parse().map_err(ConfigError::Invalid)?,
+            None => default.log_format,
+        };
+        let log_file = args.log_file.or(file.log_file);
+
+        Ok(ServerConfig {
+            bind_addr,
+            port,
+            hostname,
+            update_interval,
+            inactive_timeout,
+            cert_file,
+            key_file,
+            interest_radius,
+            restart_eta,
+            metrics_addr,
+            highscore_file,
+            leaderboard_file,
+            bans_file,
+            leaderboard_size,
+            log_format,
+            log_file,
+        })
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, ConfigError> {
+    let content =
+        fs::read_to_string(path).map_err(|error| ConfigError::Read(path.to_path_buf(), error))?;
+    toml::from_str(&content).map_err(|error| ConfigError::Parse(path.to_path_buf(), error))
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_arguments_overwrite_config_file() {
+    let file: ConfigFile = toml::from_str("port = 9000\nhostname = \"FromFile\"").unwrap();
+    let args = Args {
+        port: Some(9001),
+        ..Default::default()
+    };
+    let config = ServerConfig::from_sources(args, file).unwrap();
+
+    assert_eq!(config.port, 9001);
+    assert_eq!(config.hostname, "FromFile");
+    assert_eq!(config.update_interval, Duration::from_millis(20));
+}
+
+#[test]
+fn test_invalid_config_is_rejected() {
+    let invalid_addr = Args {
+        bind_addr: Some("localhost:8123".to_string()),
+        ..Default::default()
+    };
+    assert!(ServerConfig::from_sources(invalid_addr, ConfigFile::default()).is_err());
+
+    let invalid_interval = Args {
+        update_interval_ms: Some(0),
+        ..Default::default()
+    };
+    assert!(ServerConfig::from_sources(invalid_interval, ConfigFile::default()).is_err());
+
+    let invalid_metrics_addr = Args {
+        metrics_addr: Some("127.0.0.1".to_string()),
+        ..Default::default()
+    };
+    assert!(ServerConfig::from_sources(invalid_metrics_addr, ConfigFile::default()).is_err());
+
+    let invalid_log_format = Args {
+        log_format: Some("yaml".to_string()),
+        ..Default::default()
+    };
+    assert!(ServerConfig::from_sources(invalid_log_format, ConfigFile::default()).is_err());
+
+    let invalid_leaderboard_size: ConfigFile = toml::from_str("leaderboard_size = 0").unwrap();
+    assert!(ServerConfig::from_sources(Args::default(), invalid_leaderboard_size).is_err());
+
+    assert!(toml::from_str::<ConfigFile>("prot = 8123").is_err());
+}
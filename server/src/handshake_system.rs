@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_quinnet::server::{ConnectionLostEvent, Server};
+use shared::{Handshake, ServerMessage, PROTOCOL_VERSION};
+
+//
+// ------> Resources <------ //
+//
+
+/// Ids of all clients which sent a compatible [`Handshake`]. Messages of other clients are ignored.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct VerifiedClients(pub HashSet<u64>);
+
+//
+// ------> Events <------ //
+//
+
+/// Called when a client sent its handshake after connecting.
+#[derive(Event)]
+pub struct HandshakeEvent {
+    pub client_id: u64,
+    pub handshake: Handshake,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Answers the handshake of each client. Compatible clients are added to the [`VerifiedClients`] and get the
+/// handshake of the server, incompatible ones get the reason why they can not play on this server and are
+/// disconnected.
+pub fn on_handshake(
+    mut events: EventReader<HandshakeEvent>,
+    mut verified_clients: ResMut<VerifiedClients>,
+    mut server: ResMut<Server>,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        match check_handshake(&ev.handshake) {
+            Ok(()) => {
+                info!(build = %ev.handshake.build, "Client connected.");
+                verified_clients.insert(ev.client_id);
+                server.endpoint().try_send_message(
+                    ev.client_id,
+                    ServerMessage::HandshakeAccepted(server_handshake()),
+                );
+            }
+            Err(reason) => {
+                warn!(build = %ev.handshake.build, %reason, "Rejected client.");
+                let endpoint = server.endpoint_mut();
+                endpoint.try_send_message(ev.client_id, ServerMessage::HandshakeRejected(reason));
+                endpoint.try_disconnect_client(ev.client_id);
+            }
+        }
+    }
+}
+
+/// Removes disconnected clients from the [`VerifiedClients`].
+pub fn forget_disconnected_clients(
+    mut events: EventReader<ConnectionLostEvent>,
+    mut verified_clients: ResMut<VerifiedClients>,
+) {
+    for ev in events.read() {
+        verified_clients.remove(&ev.id);
+    }
+}
+
+/// The handshake this server answers compatible clients with.
+fn server_handshake() -> Handshake {
+    Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        build: format!("server {}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+/// Checks if the client speaks the same protocol version as the server.
+fn check_handshake(handshake: &Handshake) -> Result<(), String> {
+    match handshake.protocol_version {
+        PROTOCOL_VERSION => Ok(()),
+        version if version < PROTOCOL_VERSION => Err(format!(
+            "Your game is outdated (protocol version {}, server uses {}). Please update your game.",
+            version, PROTOCOL_VERSION
+        )),
+        version => Err(format!(
+            "The server is outdated (protocol version {}, your game uses {}). Please try again later.",
+            PROTOCOL_VERSION, version
+        )),
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_check_handshake() {
+    let handshake = |protocol_version| Handshake {
+        protocol_version,
+        build: "game test".to_string(),
+    };
+
+    assert!(check_handshake(&handshake(PROTOCOL_VERSION)).is_ok());
+    assert!(check_handshake(&handshake(PROTOCOL_VERSION - 1))
+        .unwrap_err()
+        .contains("Your game is outdated"));
+    assert!(check_handshake(&handshake(PROTOCOL_VERSION + 1))
+        .unwrap_err()
+        .contains("The server is outdated"));
+}
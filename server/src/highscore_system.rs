@@ -1,20 +1,125 @@
+use std::{collections::HashMap, path::PathBuf, sync::atomic::Ordering, time::Duration};
+
 use bevy::prelude::*;
 use bevy_quinnet::server::Server;
+use serde::{Deserialize, Serialize};
 use shared::{Highscore, ServerMessage};
 
+use crate::admin_system::{AdminCommand, AdminCommandEvent};
+use crate::metrics_system::ServerMetrics;
+use crate::rooms_system::{RoomMembers, LOBBY_ROOM_ID};
+use crate::storage;
+
+/// Iid of the first level in `jump_n_run.ldtk`. Highscore files written before records were kept
+/// per level only contain a single highscore, which belongs to this level.
+const LEGACY_LEVEL_IID: &str = "106dbb80-d7b0-11ee-a935-958c545a1bce";
+
 //
 // ------> Components <------ //
 //
 
-/// Bevy resource wrapper for the highscore.
-#[derive(Resource, Deref, DerefMut)]
-pub struct HighscoreResource(pub Highscore);
+/// Bevy resource holding the highscore of each level in the lobby, keyed by the LDtk level iid.
+/// Levels without an entry have no highscore yet. Rooms keep their own highscores, see [`rooms_system::Room`].
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct HighscoreResource(pub HashMap<String, HighscoreRecord>);
+
+/// A highscore together with who set it and when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighscoreRecord {
+    pub highscore: Highscore,
+    pub client_id: u64,
+    /// Seconds since the unix epoch.
+    pub achieved_at: u64,
+}
+
+/// Location of the file the highscores are persisted to, see [`ServerConfig::highscore_file`](crate::config::ServerConfig).
+#[derive(Resource)]
+pub struct HighscoreStorage {
+    pub path: PathBuf,
+}
+
+/// Content of the highscore file.
+#[derive(Serialize, Deserialize)]
+struct HighscoreFile {
+    records: Vec<HighscoreRecord>,
+}
+
+/// Highscore file written before highscores were kept per level.
+#[derive(Deserialize)]
+struct LegacyHighscoreFile {
+    highscore: SecondsHighscore,
+    client_id: u64,
+    achieved_at: u64,
+}
+
+/// Highscore file written before times were stored with sub-second precision.
+#[derive(Deserialize)]
+struct SecondsHighscoreFile {
+    records: Vec<SecondsHighscoreRecord>,
+}
+
+#[derive(Deserialize)]
+struct SecondsHighscoreRecord {
+    highscore: SecondsHighscore,
+    client_id: u64,
+    achieved_at: u64,
+}
+
+#[derive(Deserialize)]
+struct SecondsHighscore {
+    #[serde(default = "legacy_level_iid")]
+    level_iid: String,
+    time_in_seconds: u64,
+}
+
+fn legacy_level_iid() -> String {
+    LEGACY_LEVEL_IID.to_string()
+}
+
+impl From<SecondsHighscoreRecord> for HighscoreRecord {
+    fn from(record: SecondsHighscoreRecord) -> Self {
+        HighscoreRecord {
+            highscore: Highscore {
+                level_iid: record.highscore.level_iid,
+                time: Duration::from_secs(record.highscore.time_in_seconds),
+            },
+            client_id: record.client_id,
+            achieved_at: record.achieved_at,
+        }
+    }
+}
+
+/// All formats the highscore file was written in. Older formats are migrated when loading.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredHighscores {
+    Current(HighscoreFile),
+    Seconds(SecondsHighscoreFile),
+    Legacy(LegacyHighscoreFile),
+}
+
+impl StoredHighscores {
+    fn into_records(self) -> Vec<HighscoreRecord> {
+        match self {
+            StoredHighscores::Current(file) => file.records,
+            StoredHighscores::Seconds(file) => file.records.into_iter().map(Into::into).collect(),
+            // A time of zero meant that there was no highscore yet.
+            StoredHighscores::Legacy(file) if file.highscore.time_in_seconds == 0 => Vec::new(),
+            StoredHighscores::Legacy(file) => vec![SecondsHighscoreRecord {
+                highscore: file.highscore,
+                client_id: file.client_id,
+                achieved_at: file.achieved_at,
+            }
+            .into()],
+        }
+    }
+}
 
 //
 // ------> Events <------ //
 //
 
-/// Called when a player left the game.
+/// Called when a player finished a level and the time might be a new highscore.
 #[derive(Event)]
 pub struct RequestHighscoreEvent {
     pub client_id: u64,
@@ -25,24 +130,187 @@ pub struct RequestHighscoreEvent {
 // ------> Systems <------ //
 //
 
+/// Loads the persisted highscores from the [`HighscoreStorage`] file at startup. If there is no file
+/// yet the server starts without any highscores.
+pub fn load_highscore(storage: Res<HighscoreStorage>, mut highscores: ResMut<HighscoreResource>) {
+    match storage::load::<StoredHighscores>(&storage.path) {
+        Ok(Some(stored)) => {
+            for record in stored.into_records() {
+                highscores.insert(record.highscore.level_iid.clone(), record);
+            }
+            info!(
+                levels = highscores.len(),
+                path = %storage.path.display(),
+                "Loaded highscores."
+            );
+        }
+        Ok(None) => info!(path = %storage.path.display(), "No highscore file found."),
+        Err(error) => error!(
+            path = %storage.path.display(),
+            %error,
+            "Failed to load highscores."
+        ),
+    }
+}
+
+/// Compares each requested time of a player in the lobby with the highscore of the same level. New highscores
+/// are persisted and sent to all clients in the lobby. Times set in other rooms are handled by
+/// [`rooms_system::on_request_room_highscore`].
 pub fn on_request_highscore(
     mut events: EventReader<RequestHighscoreEvent>,
-    mut highscore: ResMut<HighscoreResource>,
-    server: Res<Server>
+    mut highscores: ResMut<HighscoreResource>,
+    storage: Res<HighscoreStorage>,
+    server: Res<Server>,
+    members: RoomMembers,
+    metrics: Res<ServerMetrics>,
 ) {
     for ev in events.read() {
-        println!(
-            "New highscore request received: {} seconds from player {}.",
-            ev.possible_highscore.time_in_seconds, ev.client_id
-        );
+        let _span = info_span!("client", id = ev.client_id).entered();
+        if members.room_of(ev.client_id) != LOBBY_ROOM_ID {
+            continue;
+        }
 
-        if highscore.0.time_in_seconds > ev.possible_highscore.time_in_seconds
-            || highscore.0.time_in_seconds == 0
-        {
-            highscore.0 = ev.possible_highscore.to_owned();
+        let possible_highscore = &ev.possible_highscore;
+        debug!(
+            level_iid = %possible_highscore.level_iid,
+            time = possible_highscore.time.as_secs_f64(),
+            "New highscore request received."
+        );
 
-            server.endpoint()
-                .try_broadcast_message(ServerMessage::InformAboutHighscore(highscore.0.clone()));
+        let is_new_highscore = match highscores.get(&possible_highscore.level_iid) {
+            Some(record) => record.highscore.time > possible_highscore.time,
+            None => true,
+        };
+        if !is_new_highscore {
+            continue;
         }
+
+        highscores.insert(
+            possible_highscore.level_iid.clone(),
+            HighscoreRecord {
+                highscore: possible_highscore.clone(),
+                client_id: ev.client_id,
+                achieved_at: storage::unix_timestamp(),
+            },
+        );
+        info!(
+            level_iid = %possible_highscore.level_iid,
+            time = possible_highscore.time.as_secs_f64(),
+            "New highscore."
+        );
+        metrics.highscore_changes.fetch_add(1, Ordering::Relaxed);
+        save_highscores(&storage, &highscores);
+
+        members.send_to_room(
+            &server,
+            LOBBY_ROOM_ID,
+            ServerMessage::InformAboutHighscore(possible_highscore.clone()),
+        );
+    }
+}
+
+/// Executes the commands of the server operator which reset or set the highscore of a level in the lobby.
+/// The change is persisted and sent to all clients in the lobby.
+pub fn on_admin_highscore_command(
+    mut events: EventReader<AdminCommandEvent>,
+    mut highscores: ResMut<HighscoreResource>,
+    storage: Res<HighscoreStorage>,
+    server: Res<Server>,
+    members: RoomMembers,
+    metrics: Res<ServerMetrics>,
+) {
+    for ev in events.read() {
+        let message = match &ev.0 {
+            AdminCommand::ResetHighscore(level_iid) => {
+                if highscores.remove(level_iid).is_none() {
+                    warn!(%level_iid, "Level has no highscore to remove.");
+                    continue;
+                }
+                info!(%level_iid, "Removed the highscore of level.");
+                ServerMessage::HighscoreRemoved(level_iid.clone())
+            }
+            AdminCommand::SetHighscore { level_iid, time } => {
+                let highscore = Highscore {
+                    level_iid: level_iid.clone(),
+                    time: *time,
+                };
+                // Highscores set by the server operator belong to no client
+                highscores.insert(
+                    level_iid.clone(),
+                    HighscoreRecord {
+                        highscore: highscore.clone(),
+                        client_id: 0,
+                        achieved_at: storage::unix_timestamp(),
+                    },
+                );
+                info!(%level_iid, time = time.as_secs_f64(), "Set the highscore of level.");
+                ServerMessage::InformAboutHighscore(highscore)
+            }
+            _ => continue,
+        };
+
+        metrics.highscore_changes.fetch_add(1, Ordering::Relaxed);
+        save_highscores(&storage, &highscores);
+        members.send_to_room(&server, LOBBY_ROOM_ID, message);
     }
 }
+
+/// Saves the highscores to the [`HighscoreStorage`] file. Returns `false` if that failed.
+pub fn save_highscores(storage: &HighscoreStorage, highscores: &HighscoreResource) -> bool {
+    let file = HighscoreFile {
+        records: highscores.values().cloned().collect(),
+    };
+    if let Err(error) = storage::save_atomic(&storage.path, &file) {
+        error!(
+            path = %storage.path.display(),
+            %error,
+            "Failed to save highscores."
+        );
+        return false;
+    }
+    true
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_migrate_legacy_highscore_file() {
+    let legacy = r#"{"highscore":{"time_in_seconds":42},"client_id":7,"achieved_at":1700000000}"#;
+    let stored: StoredHighscores = serde_json::from_str(legacy).unwrap();
+    let records = stored.into_records();
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].highscore.level_iid, LEGACY_LEVEL_IID);
+    assert_eq!(records[0].highscore.time, Duration::from_secs(42));
+}
+
+#[test]
+fn test_migrate_seconds_highscore_file() {
+    let seconds = r#"{"records":[{"highscore":{"level_iid":"abc","time_in_seconds":41},"client_id":7,"achieved_at":1700000000}]}"#;
+    let stored: StoredHighscores = serde_json::from_str(seconds).unwrap();
+    let records = stored.into_records();
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].highscore.level_iid, "abc");
+    assert_eq!(records[0].highscore.time, Duration::from_secs(41));
+}
+
+#[test]
+fn test_load_current_highscore_file() {
+    let file = HighscoreFile {
+        records: vec![HighscoreRecord {
+            highscore: Highscore {
+                level_iid: "abc".to_string(),
+                time: Duration::from_millis(41_200),
+            },
+            client_id: 7,
+            achieved_at: 1700000000,
+        }],
+    };
+    let json = serde_json::to_string(&file).unwrap();
+    let records = serde_json::from_str::<StoredHighscores>(&json).unwrap().into_records();
+
+    assert_eq!(records[0].highscore.time, Duration::from_millis(41_200));
+}
@@ -0,0 +1,249 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use serde::{Deserialize, Serialize};
+use shared::{Leaderboard, LeaderboardEntry, ServerMessage};
+
+use crate::{
+    highscore_system::RequestHighscoreEvent, players_system::Player, rooms_system::LOBBY_ROOM_ID,
+    storage,
+};
+
+//
+// ------> Resources <------ //
+//
+
+/// Bevy resource holding the leaderboard entries of each level, keyed by the LDtk level iid.
+/// The entries of a level are sorted from fastest to slowest.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct LeaderboardResource(pub HashMap<String, Vec<LeaderboardEntry>>);
+
+/// Location of the file the leaderboards are persisted to and the number of entries kept per level,
+/// both set in the [`ServerConfig`](crate::config::ServerConfig).
+#[derive(Resource)]
+pub struct LeaderboardStorage {
+    pub path: PathBuf,
+    pub size: usize,
+}
+
+/// Content of the leaderboard file.
+#[derive(Serialize, Deserialize)]
+struct LeaderboardFile {
+    leaderboards: Vec<Leaderboard>,
+}
+
+/// Leaderboard file written before times were stored with sub-second precision.
+#[derive(Deserialize)]
+struct SecondsLeaderboardFile {
+    leaderboards: Vec<SecondsLeaderboard>,
+}
+
+#[derive(Deserialize)]
+struct SecondsLeaderboard {
+    level_iid: String,
+    entries: Vec<SecondsLeaderboardEntry>,
+}
+
+#[derive(Deserialize)]
+struct SecondsLeaderboardEntry {
+    player_name: String,
+    time_in_seconds: u64,
+    achieved_at: u64,
+}
+
+/// All formats the leaderboard file was written in. Older formats are migrated when loading.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredLeaderboards {
+    Current(LeaderboardFile),
+    Seconds(SecondsLeaderboardFile),
+}
+
+impl StoredLeaderboards {
+    fn into_leaderboards(self) -> Vec<Leaderboard> {
+        match self {
+            StoredLeaderboards::Current(file) => file.leaderboards,
+            StoredLeaderboards::Seconds(file) => file
+                .leaderboards
+                .into_iter()
+                .map(|leaderboard| Leaderboard {
+                    level_iid: leaderboard.level_iid,
+                    entries: leaderboard
+                        .entries
+                        .into_iter()
+                        .map(|entry| LeaderboardEntry {
+                            player_name: entry.player_name,
+                            time: Duration::from_secs(entry.time_in_seconds),
+                            achieved_at: entry.achieved_at,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+//
+// ------> Events <------ //
+//
+
+/// Called when a player requests the leaderboard of a level.
+#[derive(Event)]
+pub struct RequestLeaderboardEvent {
+    pub client_id: u64,
+    pub level_iid: String,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Loads the persisted leaderboards from the [`LeaderboardStorage`] file at startup.
+pub fn load_leaderboard(
+    storage: Res<LeaderboardStorage>,
+    mut leaderboards: ResMut<LeaderboardResource>,
+) {
+    match storage::load::<StoredLeaderboards>(&storage.path) {
+        Ok(Some(stored)) => {
+            for mut leaderboard in stored.into_leaderboards() {
+                leaderboard.entries.truncate(storage.size);
+                leaderboards.insert(leaderboard.level_iid, leaderboard.entries);
+            }
+            info!(
+                levels = leaderboards.len(),
+                path = %storage.path.display(),
+                "Loaded leaderboards."
+            );
+        }
+        Ok(None) => info!(path = %storage.path.display(), "No leaderboard file found."),
+        Err(error) => error!(
+            path = %storage.path.display(),
+            %error,
+            "Failed to load leaderboards."
+        ),
+    }
+}
+
+/// Adds every run finished in the lobby to the leaderboard of its level. Like the highscores, the leaderboards
+/// are kept for the lobby only, runs in rooms do not count. Runs slower than all entries of a full leaderboard
+/// are dropped. The leaderboards are persisted when they changed.
+pub fn on_level_finished(
+    mut events: EventReader<RequestHighscoreEvent>,
+    mut leaderboards: ResMut<LeaderboardResource>,
+    storage: Res<LeaderboardStorage>,
+    players: Query<&Player>,
+) {
+    let mut changed = false;
+
+    for ev in events.read() {
+        let Some(player) = players.iter().find(|p| p.client_id == ev.client_id) else {
+            // Only players who joined the game with a name can get onto the leaderboard.
+            continue;
+        };
+        if player.room_id != LOBBY_ROOM_ID {
+            continue;
+        }
+        let entry = LeaderboardEntry {
+            player_name: player.name.clone(),
+            time: ev.possible_highscore.time,
+            achieved_at: storage::unix_timestamp(),
+        };
+        let entries = leaderboards
+            .entry(ev.possible_highscore.level_iid.clone())
+            .or_default();
+
+        changed |= insert_entry(entries, entry, storage.size);
+    }
+
+    if changed {
+        save_leaderboards(&storage, &leaderboards);
+    }
+}
+
+/// Sends the requested leaderboard to the player. Levels without any finishes get an empty leaderboard.
+pub fn on_request_leaderboard(
+    mut events: EventReader<RequestLeaderboardEvent>,
+    leaderboards: Res<LeaderboardResource>,
+    server: Res<Server>,
+) {
+    for ev in events.read() {
+        let leaderboard = Leaderboard {
+            level_iid: ev.level_iid.clone(),
+            entries: leaderboards.get(&ev.level_iid).cloned().unwrap_or_default(),
+        };
+
+        server
+            .endpoint()
+            .try_send_message(ev.client_id, ServerMessage::Leaderboard(leaderboard));
+    }
+}
+
+/// Inserts the entry at its position in the sorted `entries` and cuts them to `size`.
+/// Entries with the same time keep their order, so the first one to set a time stays ahead.
+/// Returns if the entry made it onto the leaderboard.
+fn insert_entry(entries: &mut Vec<LeaderboardEntry>, entry: LeaderboardEntry, size: usize) -> bool {
+    let position = entries.partition_point(|e| e.time <= entry.time);
+    if position >= size {
+        return false;
+    }
+
+    entries.insert(position, entry);
+    entries.truncate(size);
+    true
+}
+
+/// Saves the leaderboards to the [`LeaderboardStorage`] file. Returns `false` if that failed.
+pub fn save_leaderboards(storage: &LeaderboardStorage, leaderboards: &LeaderboardResource) -> bool {
+    let file = LeaderboardFile {
+        leaderboards: leaderboards
+            .iter()
+            .map(|(level_iid, entries)| Leaderboard {
+                level_iid: level_iid.clone(),
+                entries: entries.clone(),
+            })
+            .collect(),
+    };
+    if let Err(error) = storage::save_atomic(&storage.path, &file) {
+        error!(
+            path = %storage.path.display(),
+            %error,
+            "Failed to save leaderboards."
+        );
+        return false;
+    }
+    true
+}
+
+//
+// ------> Tests <------ //
+//
+
+fn _tests_util_entry(time_in_millis: u64) -> LeaderboardEntry {
+    LeaderboardEntry {
+        player_name: format!("Player {}", time_in_millis),
+        time: Duration::from_millis(time_in_millis),
+        achieved_at: 0,
+    }
+}
+
+#[test]
+fn test_insert_entry_keeps_fastest() {
+    let mut entries = Vec::new();
+    for time in [41_900, 41_200, 20_000, 50_000] {
+        insert_entry(&mut entries, _tests_util_entry(time), 3);
+    }
+
+    let times: Vec<u128> = entries.iter().map(|e| e.time.as_millis()).collect();
+    assert_eq!(times, vec![20_000, 41_200, 41_900]);
+    assert!(!insert_entry(&mut entries, _tests_util_entry(41_900), 3));
+}
+
+#[test]
+fn test_migrate_seconds_leaderboard_file() {
+    let seconds = r#"{"leaderboards":[{"level_iid":"abc","entries":[{"player_name":"Speedy","time_in_seconds":41,"achieved_at":0}]}]}"#;
+    let stored: StoredLeaderboards = serde_json::from_str(seconds).unwrap();
+    let leaderboards = stored.into_leaderboards();
+
+    assert_eq!(leaderboards[0].entries[0].time, Duration::from_secs(41));
+}
@@ -1,120 +1,348 @@
-use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*, time::TimePlugin};
+use std::process::ExitCode;
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    time::TimePlugin,
+};
+use admin_system::{AdminCommandEvent, AdminConsole, BanStorage, Bans};
 use bevy_quinnet::server::{
     certificate::CertificateRetrievalMode, QuinnetServerPlugin, Server, ServerConfiguration,
 };
-use highscore_system::{HighscoreResource, RequestHighscoreEvent};
+use chat_system::ChatEvent;
+use config::ServerConfig;
+use handshake_system::{HandshakeEvent, VerifiedClients};
+use highscore_system::{HighscoreResource, HighscoreStorage, RequestHighscoreEvent};
+use leaderboard_system::{LeaderboardResource, LeaderboardStorage, RequestLeaderboardEvent};
+use metrics_system::{ServerMetrics, TickStart};
 use players_system::{
-    PlayerJoinedEvent, PlayerLeftEvent, PlayerMovedEvent, UpdateMovedPlayersTimer,
+    MovementSequences, PlayerJoinedEvent, PlayerLeftEvent, PlayerMovedEvent, SentSnapshots,
+    ServerTick, SpectatorJoinedEvent, UpdateMovedPlayersTimer,
 };
-use shared::{Highscore, PlayerMessage, ServerMessage};
+use race_system::{RaceReadyEvent, Races};
+use rooms_system::{RoomEvent, RoomRequest, Rooms};
+use run_validation_system::{RunEvent, RunMessage};
+use shared::{PlayerMessage, ServerMessage};
+use shutdown_system::{ExitStatus, ShutdownEvent, ShutdownSignal};
 
+mod admin_system;
+mod chat_system;
+mod config;
+mod handshake_system;
 mod highscore_system;
+mod leaderboard_system;
+mod metrics_system;
 mod players_system;
-
-const SERVER_HOSTNAME: &'static str = "JumpNRun_Server";
-const SERVER_IP_ADDR: &'static str = "0.0.0.0";
-const SERVER_PORT: u16 = 8123;
+mod race_system;
+mod rooms_system;
+mod run_validation_system;
+mod shutdown_system;
+mod storage;
 
 /// Creates the bevy app for the server with all required plugins, events, systems and resources.
-pub fn main() {
+/// Exits with an error if the configuration is invalid, see [`ServerConfig::load`], if the log can not be
+/// written, or if the server could not start or save its state, see [`ExitStatus`].
+pub fn main() -> ExitCode {
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Invalid server configuration: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = shared::logging::init_logging(&config.log_config()) {
+        eprintln!("Failed to set up logging: {}", error);
+        return ExitCode::FAILURE;
+    }
+
     let mut app = App::new();
     app.add_plugins((
         ScheduleRunnerPlugin::default(),
-        LogPlugin::default(),
-        TimePlugin::default(),
+        TimePlugin,
         QuinnetServerPlugin::default(),
     ));
 
+    app.add_event::<HandshakeEvent>();
     app.add_event::<PlayerJoinedEvent>();
+    app.add_event::<SpectatorJoinedEvent>();
     app.add_event::<PlayerMovedEvent>();
     app.add_event::<PlayerLeftEvent>();
+    app.add_event::<RunEvent>();
+    app.add_event::<RoomEvent>();
+    app.add_event::<RaceReadyEvent>();
+    app.add_event::<ChatEvent>();
     app.add_event::<RequestHighscoreEvent>();
+    app.add_event::<RequestLeaderboardEvent>();
+    app.add_event::<AdminCommandEvent>();
+    app.add_event::<ShutdownEvent>();
 
-    app.add_systems(Startup, start_listening);
+    app.add_systems(
+        Startup,
+        (
+            start_listening,
+            highscore_system::load_highscore,
+            leaderboard_system::load_leaderboard,
+            admin_system::load_bans,
+            metrics_system::start_metrics_endpoint,
+        ),
+    );
+    app.add_systems(First, metrics_system::start_tick);
+    app.add_systems(Last, metrics_system::end_tick);
     app.add_systems(
         Update,
         (
             handle_player_messages,
+            handshake_system::on_handshake,
+            handshake_system::forget_disconnected_clients,
             players_system::on_player_joined,
+            players_system::on_spectator_joined,
             players_system::on_player_moved,
             players_system::on_player_left,
             players_system::send_updates_to_players,
             players_system::remove_inactive_players,
-            highscore_system::on_request_highscore,
+            players_system::send_roster_on_change,
+            players_system::forget_disconnected_players,
+            (
+                run_validation_system::track_runs,
+                run_validation_system::on_run_event,
+                (
+                    highscore_system::on_request_highscore,
+                    rooms_system::on_request_room_highscore,
+                    race_system::on_race_finish,
+                    leaderboard_system::on_level_finished,
+                ),
+            )
+                .chain(),
+            leaderboard_system::on_request_leaderboard,
+            rooms_system::on_room_request,
+            rooms_system::remove_empty_rooms,
+            race_system::on_race_ready,
+            race_system::update_races.after(race_system::on_race_ready),
+            chat_system::on_chat_message,
+            (
+                admin_system::read_admin_console,
+                (
+                    admin_system::on_admin_command,
+                    highscore_system::on_admin_highscore_command,
+                ),
+            )
+                .chain(),
+            (
+                shutdown_system::check_shutdown_signal,
+                shutdown_system::on_shutdown,
+            )
+                .chain()
+                .after(admin_system::on_admin_command),
         ),
     );
 
-    app.insert_resource(UpdateMovedPlayersTimer(Timer::from_seconds(
-        0.02,
+    app.insert_resource(UpdateMovedPlayersTimer(Timer::new(
+        config.update_interval,
         TimerMode::Repeating,
     )));
-    app.insert_resource(HighscoreResource(Highscore {
-        time_in_seconds: 0, // 0 means -> No highscore set yet.
-    }));
+    app.insert_resource(ServerTick::default());
+    app.insert_resource(MovementSequences::default());
+    app.insert_resource(SentSnapshots::default());
+    app.insert_resource(VerifiedClients::default());
+    app.insert_resource(HighscoreResource::default());
+    app.insert_resource(Rooms::default());
+    app.insert_resource(Races::default());
+    app.insert_resource(HighscoreStorage {
+        path: config.highscore_file.clone(),
+    });
+    app.insert_resource(LeaderboardResource::default());
+    app.insert_resource(LeaderboardStorage {
+        path: config.leaderboard_file.clone(),
+        size: config.leaderboard_size,
+    });
+    app.insert_resource(Bans::default());
+    app.insert_resource(BanStorage {
+        path: config.bans_file.clone(),
+    });
+    app.insert_resource(AdminConsole::from_stdin());
+    app.insert_resource(ShutdownSignal::listen());
+    app.insert_resource(ServerMetrics::default());
+    app.insert_resource(TickStart(std::time::Instant::now()));
+    app.insert_resource(config);
+
+    let exit_status = ExitStatus::default();
+    app.insert_resource(exit_status.clone());
 
     app.run();
+    exit_status.code()
 }
 
-/// Starts the endpoint of the server via the ``bevy_quinnet`` library.
-fn start_listening(mut server: ResMut<Server>) {
-    let local_bind_addr_str = format!("{}:{}", SERVER_IP_ADDR, SERVER_PORT);
-    let server_config_result = ServerConfiguration::from_string(&local_bind_addr_str);
-
-    match server_config_result {
-        Ok(config) => {
-            let cert_mode = CertificateRetrievalMode::GenerateSelfSigned {
-                server_hostname: SERVER_HOSTNAME.to_string(),
-            };
-
-            let start_endpoint_result = server.start_endpoint(config, cert_mode);
-            match start_endpoint_result {
-                Ok(_) => {}
-                Err(error) => println!("Failed to start server endpoint: {}", error),
+/// Starts the endpoint of the server via the ``bevy_quinnet`` library on the address of the [`ServerConfig`].
+/// The certificate is loaded from the configured files. On the first start a self signed certificate is generated
+/// and saved there, so its fingerprint stays the same across restarts and can be pinned by the clients.
+/// Stops the server with a failure if the endpoint can not be started, e.g. because the port is already in use.
+fn start_listening(
+    mut server: ResMut<Server>,
+    config: Res<ServerConfig>,
+    exit_status: Res<ExitStatus>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    for path in [&config.cert_file, &config.key_file] {
+        if let Some(parent) = path.parent() {
+            if let Err(error) = std::fs::create_dir_all(parent) {
+                warn!("Failed to create directory {}: {}", parent.display(), error);
             }
         }
-        Err(error) => println!("Failed to parse server adress: {}", error),
+    }
+
+    let server_config = ServerConfiguration::from_addr(config.socket_addr());
+    let cert_mode = CertificateRetrievalMode::LoadFromFileOrGenerateSelfSigned {
+        cert_file: config.cert_file.to_string_lossy().into_owned(),
+        key_file: config.key_file.to_string_lossy().into_owned(),
+        save_on_disk: true,
+        server_hostname: config.hostname.clone(),
+    };
+
+    match server.start_endpoint(server_config, cert_mode) {
+        Ok((certificate, _)) => {
+            info!("Server listening on {}.", config.socket_addr());
+            info!(
+                "Certificate fingerprint: {}",
+                certificate.fingerprint.to_base64()
+            );
+        }
+        Err(error) => {
+            error!(
+                "Failed to start server endpoint on {}: {}",
+                config.socket_addr(),
+                error
+            );
+            exit_status.fail();
+            app_exit_events.send(AppExit);
+        }
     }
 }
 
 /// Handles all messages sent from the clients to the server. Each messages creates a new event
 /// which is than handled by the responsible system.
+///
+/// Clients have to send a compatible [`PlayerMessage::Handshake`] first, all other messages
+/// of clients not in the [`VerifiedClients`] are ignored. Movement updates older than the latest one
+/// of the client are dropped, see [`MovementSequences`]. Every received message is counted in the [`ServerMetrics`].
 fn handle_player_messages(
     mut server: ResMut<Server>,
+    verified_clients: Res<VerifiedClients>,
+    mut movement_sequences: ResMut<MovementSequences>,
+    metrics: Res<ServerMetrics>,
 
+    mut ev_handshake: EventWriter<HandshakeEvent>,
     mut ev_player_joined: EventWriter<PlayerJoinedEvent>,
+    mut ev_spectator_joined: EventWriter<SpectatorJoinedEvent>,
     mut ev_player_moved: EventWriter<PlayerMovedEvent>,
     mut ev_player_left: EventWriter<PlayerLeftEvent>,
-    mut ev_highscore_request: EventWriter<RequestHighscoreEvent>,
+    mut ev_run: EventWriter<RunEvent>,
+    mut ev_room: EventWriter<RoomEvent>,
+    mut ev_race_ready: EventWriter<RaceReadyEvent>,
+    mut ev_chat: EventWriter<ChatEvent>,
+    mut ev_leaderboard_request: EventWriter<RequestLeaderboardEvent>,
 ) {
     // This mutable is required due to the `endpoint.try_receive_message_from` function call.
     // Seems like a rust analyer mistake to state that mut is not required.
     #![allow(unused_mut)]
+    // Bevy systems get one argument per event they send.
+    #![allow(clippy::too_many_arguments)]
     let mut endpoint = server.endpoint_mut();
 
     for client_id in endpoint.clients() {
         while let Some(message) = endpoint.try_receive_message_from::<PlayerMessage>(client_id) {
+            metrics.count_player_message(&message);
+            if let PlayerMessage::Handshake(handshake) = message {
+                ev_handshake.send(HandshakeEvent {
+                    client_id,
+                    handshake,
+                });
+                continue;
+            }
+            if !verified_clients.contains(&client_id) {
+                continue;
+            }
+
             match message {
+                PlayerMessage::Handshake(_) => {}
                 PlayerMessage::Ping => {
                     let _ = endpoint.send_message(client_id, ServerMessage::Pong);
                 }
-                PlayerMessage::JoinGame(movement) => {
+                PlayerMessage::JoinGame(join_request) => {
                     ev_player_joined.send(PlayerJoinedEvent {
                         client_id,
-                        movement,
+                        name: join_request.name,
+                        movement: join_request.movement,
+                        session_token: join_request.session_token,
                     });
                 }
-                PlayerMessage::PlayerMoved(movement) => {
-                    ev_player_moved.send(PlayerMovedEvent {
+                PlayerMessage::Spectate(request) => {
+                    ev_spectator_joined.send(SpectatorJoinedEvent {
                         client_id,
-                        movement,
+                        name: request.name,
+                        session_token: request.session_token,
                     });
                 }
-                PlayerMessage::RequestPossibleHighscore(possible_highscore) => {
-                    ev_highscore_request.send(RequestHighscoreEvent {
+                PlayerMessage::PlayerMoved(update) => {
+                    let is_latest = movement_sequences
+                        .entry(client_id)
+                        .or_default()
+                        .accept(update.sequence);
+                    if is_latest {
+                        ev_player_moved.send(PlayerMovedEvent {
+                            client_id,
+                            movement: update.movement,
+                            ack_tick: update.ack_tick,
+                        });
+                    }
+                }
+                PlayerMessage::RunStarted => {
+                    ev_run.send(RunEvent {
                         client_id,
-                        possible_highscore,
+                        message: RunMessage::Started,
                     });
                 }
+                PlayerMessage::RunFinished(claim) => {
+                    ev_run.send(RunEvent {
+                        client_id,
+                        message: RunMessage::Finished(claim),
+                    });
+                }
+                PlayerMessage::RequestLeaderboard(level_iid) => {
+                    ev_leaderboard_request.send(RequestLeaderboardEvent {
+                        client_id,
+                        level_iid,
+                    });
+                }
+                PlayerMessage::CreateRoom(request) => {
+                    ev_room.send(RoomEvent {
+                        client_id,
+                        request: RoomRequest::Create(request),
+                    });
+                }
+                PlayerMessage::ListRooms => {
+                    ev_room.send(RoomEvent {
+                        client_id,
+                        request: RoomRequest::List,
+                    });
+                }
+                PlayerMessage::JoinRoom(request) => {
+                    ev_room.send(RoomEvent {
+                        client_id,
+                        request: RoomRequest::Join(request),
+                    });
+                }
+                PlayerMessage::LeaveRoom => {
+                    ev_room.send(RoomEvent {
+                        client_id,
+                        request: RoomRequest::Leave,
+                    });
+                }
+                PlayerMessage::SetRaceReady(ready) => {
+                    ev_race_ready.send(RaceReadyEvent { client_id, ready });
+                }
+                PlayerMessage::Chat(text) => {
+                    ev_chat.send(ChatEvent { client_id, text });
+                }
                 PlayerMessage::LeaveGame => {
                     ev_player_left.send(PlayerLeftEvent { client_id });
                 }
@@ -127,6 +355,18 @@ fn handle_player_messages(
 fn test_server_startup() {
     let mut app = App::new();
     app.add_plugins(QuinnetServerPlugin::default());
+
+    // Keeps the generated certificate and the data files out of the data directory
+    let data_dir = std::env::temp_dir().join(format!("jumpnrun-server-test-{}", std::process::id()));
+    app.insert_resource(ServerConfig {
+        cert_file: data_dir.join("cert.pem"),
+        key_file: data_dir.join("key.pem"),
+        highscore_file: data_dir.join("highscore.json"),
+        leaderboard_file: data_dir.join("leaderboard.json"),
+        bans_file: data_dir.join("bans.json"),
+        ..ServerConfig::default()
+    });
+    app.insert_resource(ExitStatus::default());
     app.add_systems(Update, start_listening);
     app.update();
 
@@ -136,6 +376,7 @@ fn test_server_startup() {
         Some(server) => {
             assert!(server.is_listening());
         }
-        None => assert!(false),
+        None => panic!("The server resource is missing."),
     }
+    let _ = std::fs::remove_dir_all(data_dir);
 }
@@ -0,0 +1,336 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use shared::PlayerMessage;
+
+use crate::config::ServerConfig;
+use crate::players_system::{Player, Spectator};
+
+/// The server counts as unhealthy if its main loop did not run for this long.
+const MAX_TICK_AGE: Duration = Duration::from_secs(5);
+
+/// How long the metrics endpoint waits for the request of a client.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+//
+// ------> Resources <------ //
+//
+
+/// Counters and gauges of the server. Updated by the systems and read by the thread serving the metrics endpoint.
+pub struct MetricsState {
+    started: Instant,
+    pub connected_clients: AtomicU64,
+    pub players: AtomicU64,
+    pub spectators: AtomicU64,
+    /// Bytes of all movement updates sent by [`crate::players_system::send_updates_to_players`].
+    pub update_bytes_sent: AtomicU64,
+    pub tick_duration_micros: AtomicU64,
+    pub inactive_removals: AtomicU64,
+    /// New highscores in the lobby and all rooms, including the ones set by the server operator.
+    pub highscore_changes: AtomicU64,
+    /// Milliseconds since `started` at which the main loop finished its last tick.
+    last_tick_millis: AtomicU64,
+    listening: AtomicBool,
+    player_messages: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Default for MetricsState {
+    fn default() -> Self {
+        MetricsState {
+            started: Instant::now(),
+            connected_clients: AtomicU64::default(),
+            players: AtomicU64::default(),
+            spectators: AtomicU64::default(),
+            update_bytes_sent: AtomicU64::default(),
+            tick_duration_micros: AtomicU64::default(),
+            inactive_removals: AtomicU64::default(),
+            highscore_changes: AtomicU64::default(),
+            last_tick_millis: AtomicU64::default(),
+            listening: AtomicBool::default(),
+            player_messages: Mutex::default(),
+        }
+    }
+}
+
+impl MetricsState {
+    /// Counts a message received from a client, labeled with its variant.
+    pub fn count_player_message(&self, message: &PlayerMessage) {
+        if let Ok(mut counts) = self.player_messages.lock() {
+            *counts.entry(message_variant(message)).or_default() += 1;
+        }
+    }
+
+    /// Returns `true` if the endpoint of the server is listening and the main loop is running.
+    pub fn is_healthy(&self) -> bool {
+        let now = self.started.elapsed().as_millis() as u64;
+        let last_tick = self.last_tick_millis.load(Ordering::Relaxed);
+        self.listening.load(Ordering::Relaxed)
+            && now.saturating_sub(last_tick) <= MAX_TICK_AGE.as_millis() as u64
+    }
+
+    /// All metrics in the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{}", value.trim_end());
+        };
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+
+        metric(
+            "jumpnrun_connected_clients",
+            "gauge",
+            "Clients connected to the server, including the ones which did not join yet.",
+            format!(
+                "jumpnrun_connected_clients {}",
+                load(&self.connected_clients)
+            ),
+        );
+        metric(
+            "jumpnrun_players",
+            "gauge",
+            "Players in the game, including the ones waiting to resume their session.",
+            format!("jumpnrun_players {}", load(&self.players)),
+        );
+        metric(
+            "jumpnrun_spectators",
+            "gauge",
+            "Clients watching the game as spectators.",
+            format!("jumpnrun_spectators {}", load(&self.spectators)),
+        );
+
+        let counts = self
+            .player_messages
+            .lock()
+            .map(|counts| counts.clone())
+            .unwrap_or_default();
+        let mut messages = String::new();
+        for (variant, count) in counts {
+            let _ = writeln!(
+                messages,
+                "jumpnrun_player_messages_total{{variant=\"{}\"}} {}",
+                variant, count
+            );
+        }
+        metric(
+            "jumpnrun_player_messages_total",
+            "counter",
+            "Messages received from the clients by variant of the message.",
+            messages,
+        );
+
+        metric(
+            "jumpnrun_update_bytes_sent_total",
+            "counter",
+            "Bytes of the movement updates sent to the clients.",
+            format!(
+                "jumpnrun_update_bytes_sent_total {}",
+                load(&self.update_bytes_sent)
+            ),
+        );
+        metric(
+            "jumpnrun_tick_duration_seconds",
+            "gauge",
+            "Time the last tick of the server took.",
+            format!(
+                "jumpnrun_tick_duration_seconds {:.6}",
+                load(&self.tick_duration_micros) as f64 / 1_000_000.0
+            ),
+        );
+        metric(
+            "jumpnrun_inactive_removals_total",
+            "counter",
+            "Players removed due to inactivity.",
+            format!(
+                "jumpnrun_inactive_removals_total {}",
+                load(&self.inactive_removals)
+            ),
+        );
+        metric(
+            "jumpnrun_highscore_changes_total",
+            "counter",
+            "New highscores in all rooms.",
+            format!(
+                "jumpnrun_highscore_changes_total {}",
+                load(&self.highscore_changes)
+            ),
+        );
+        output
+    }
+}
+
+/// The [`MetricsState`] of the server, shared with the thread serving the metrics endpoint.
+#[derive(Resource, Clone, Default, Deref)]
+pub struct ServerMetrics(pub Arc<MetricsState>);
+
+/// Time at which the current tick of the server started.
+#[derive(Resource, Deref, DerefMut)]
+pub struct TickStart(pub Instant);
+
+//
+// ------> Systems <------ //
+//
+
+/// Starts serving the metrics on the address of the [`ServerConfig`], if one is configured.
+/// The server keeps running without metrics if the address can not be bound.
+pub fn start_metrics_endpoint(config: Res<ServerConfig>, metrics: Res<ServerMetrics>) {
+    let Some(addr) = config.metrics_addr else {
+        return;
+    };
+    match serve_metrics(addr, metrics.0.clone()) {
+        Ok(()) => info!(%addr, "Serving metrics on http://{}/metrics.", addr),
+        Err(error) => error!(%addr, %error, "Failed to serve metrics."),
+    }
+}
+
+/// Remembers when the tick started. Runs first in every tick, see [`end_tick`].
+pub fn start_tick(mut tick_start: ResMut<TickStart>) {
+    tick_start.0 = Instant::now();
+}
+
+/// Measures how long the tick took and updates the gauges. Runs last in every tick.
+pub fn end_tick(
+    tick_start: Res<TickStart>,
+    metrics: Res<ServerMetrics>,
+    server: Res<Server>,
+    players: Query<(), With<Player>>,
+    spectators: Query<(), With<Spectator>>,
+) {
+    let tick_duration = tick_start.elapsed().as_micros() as u64;
+    metrics
+        .tick_duration_micros
+        .store(tick_duration, Ordering::Relaxed);
+
+    let clients = server
+        .get_endpoint()
+        .map_or(0, |endpoint| endpoint.clients().len());
+    metrics
+        .connected_clients
+        .store(clients as u64, Ordering::Relaxed);
+    metrics
+        .players
+        .store(players.iter().count() as u64, Ordering::Relaxed);
+    metrics
+        .spectators
+        .store(spectators.iter().count() as u64, Ordering::Relaxed);
+
+    metrics
+        .listening
+        .store(server.is_listening(), Ordering::Relaxed);
+    let now = metrics.started.elapsed().as_millis() as u64;
+    metrics.last_tick_millis.store(now, Ordering::Relaxed);
+}
+
+/// Name of the variant of the message, used as label of the message counter.
+pub fn message_variant(message: &PlayerMessage) -> &'static str {
+    match message {
+        PlayerMessage::Handshake(_) => "Handshake",
+        PlayerMessage::Ping => "Ping",
+        PlayerMessage::JoinGame(_) => "JoinGame",
+        PlayerMessage::Spectate(_) => "Spectate",
+        PlayerMessage::PlayerMoved(_) => "PlayerMoved",
+        PlayerMessage::RunStarted => "RunStarted",
+        PlayerMessage::RunFinished(_) => "RunFinished",
+        PlayerMessage::RequestLeaderboard(_) => "RequestLeaderboard",
+        PlayerMessage::CreateRoom(_) => "CreateRoom",
+        PlayerMessage::ListRooms => "ListRooms",
+        PlayerMessage::JoinRoom(_) => "JoinRoom",
+        PlayerMessage::LeaveRoom => "LeaveRoom",
+        PlayerMessage::SetRaceReady(_) => "SetRaceReady",
+        PlayerMessage::Chat(_) => "Chat",
+        PlayerMessage::LeaveGame => "LeaveGame",
+    }
+}
+
+/// Serves `/metrics` and `/healthz` over HTTP on a separate thread. Each request is answered and the
+/// connection closed, which is all Prometheus and health checks need.
+fn serve_metrics(addr: SocketAddr, metrics: Arc<MetricsState>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(error) = answer_request(stream, &metrics) {
+                warn!(%error, "Failed to answer metrics request.");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn answer_request(stream: TcpStream, metrics: &MetricsState) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let path = match request_line
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        ["GET", path, ..] => path.to_string(),
+        _ => String::new(),
+    };
+    let (status, body) = route(&path, metrics);
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Status line and body of the answer to a request of the given path.
+fn route(path: &str, metrics: &MetricsState) -> (&'static str, String) {
+    match path {
+        "/metrics" => ("200 OK", metrics.render()),
+        "/healthz" if metrics.is_healthy() => ("200 OK", "ok\n".to_string()),
+        "/healthz" => ("503 Service Unavailable", "unhealthy\n".to_string()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_metrics_are_rendered_in_prometheus_format() {
+    let metrics = MetricsState::default();
+    metrics.count_player_message(&PlayerMessage::Ping);
+    metrics.count_player_message(&PlayerMessage::Ping);
+    metrics.count_player_message(&PlayerMessage::Chat("gg".to_string()));
+    metrics.update_bytes_sent.store(1234, Ordering::Relaxed);
+
+    let (status, body) = route("/metrics", &metrics);
+    assert_eq!(status, "200 OK");
+    assert!(body.contains("# TYPE jumpnrun_player_messages_total counter\n"));
+    assert!(body.contains("jumpnrun_player_messages_total{variant=\"Ping\"} 2\n"));
+    assert!(body.contains("jumpnrun_player_messages_total{variant=\"Chat\"} 1\n"));
+    assert!(body.contains("jumpnrun_update_bytes_sent_total 1234\n"));
+    assert_eq!(route("/", &metrics).0, "404 Not Found");
+}
+
+#[test]
+fn test_healthz_needs_a_listening_server() {
+    let metrics = MetricsState::default();
+    assert_eq!(route("/healthz", &metrics).0, "503 Service Unavailable");
+
+    metrics.listening.store(true, Ordering::Relaxed);
+    assert_eq!(route("/healthz", &metrics).0, "200 OK");
+}
@@ -1,9 +1,27 @@
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
 use bevy::prelude::*;
-use bevy_quinnet::server::Server;
+use bevy::utils::HashMap;
+use bevy_quinnet::{
+    server::{ConnectionLostEvent, Server},
+    shared::channel::ChannelId,
+};
+use rand::Rng;
 
-use shared::{PlayerMovedUpdate, PlayerMovement, ServerMessage};
+use shared::{
+    MovedPlayersSnapshot, PlayerMovement, PlayerStates, QuantizedMovement, RosterEntry,
+    SequenceTracker, ServerMessage, SessionInfo,
+};
 
+use crate::admin_system::Bans;
+use crate::chat_system::ChatLimiter;
+use crate::config::ServerConfig;
 use crate::highscore_system::HighscoreResource;
+use crate::metrics_system::ServerMetrics;
+use crate::rooms_system::{self, RoomMembers, Rooms, LOBBY_ROOM_ID};
+use crate::run_validation_system::RunTracker;
 
 //
 // ------> Components <------ //
@@ -13,8 +31,28 @@ use crate::highscore_system::HighscoreResource;
 /// when the client calls the `PlayerMessage::JoinGame` function.
 #[derive(Component)]
 pub struct Player {
-    /// The id given to the client from the ``bevy_quinnet`` library.
-    client_id: u64,
+    /// The id given to the client from the ``bevy_quinnet`` library. Changes when the player reconnects.
+    pub client_id: u64,
+    /// The id shown to the other players. This is the `client_id` of the first connection and stays
+    /// the same when the player reconnects.
+    pub id: u64,
+    /// The display name chosen by the player, validated by [`validate_player_name`].
+    pub name: String,
+    /// Id of the room the player is in, see [`Rooms`]. Players start in the lobby.
+    pub room_id: u64,
+}
+
+/// Represents a client watching the game without playing. Spawned when the client sends
+/// `PlayerMessage::Spectate`. Spectators are not shown to the other clients and are never removed due
+/// to inactivity, as they do not send movement updates.
+#[derive(Component)]
+pub struct Spectator {
+    pub client_id: u64,
+    /// Name and session token sent by the client, only used by the server operator to kick or ban the spectator.
+    pub name: String,
+    pub session_token: Option<String>,
+    /// Id of the room the spectator watches, see [`Rooms`]. Spectators start in the lobby.
+    pub room_id: u64,
 }
 
 /// Represents the velocity of a player in the game.
@@ -31,6 +69,13 @@ pub struct Translation {
     y: f32,
 }
 
+/// Session of a player. Clients which lost the connection can send the token in their join request
+/// to get back their player entity, as long as it was not removed due to inactivity.
+#[derive(Component)]
+pub struct Session {
+    pub token: String,
+}
+
 /// Individuell timer for each player to check when the last movement update
 /// has happend. If there was no update for a period of time, the player
 /// will be removed from the server.
@@ -41,10 +86,132 @@ pub struct InactiveTimer(Timer);
 // ------> Resources <------ //
 //
 
+/// Minimum number of characters of a player name.
+const MIN_NAME_LENGTH: usize = 3;
+
+/// Maximum number of characters of a player name.
+const MAX_NAME_LENGTH: usize = 16;
+
+/// Every this many ticks a keyframe with all players is sent, so clients which missed snapshots catch up.
+const KEYFRAME_INTERVAL: u32 = 50;
+
+/// Maximum number of sent snapshots kept per client as possible baselines.
+const MAX_SENT_SNAPSHOTS: usize = 64;
+
+/// Players outside of the interest radius are only updated every this many ticks.
+const DISTANT_UPDATE_INTERVAL: u32 = 10;
+
 /// Timer for sending updates to clients about the positons of the other players.
 #[derive(Resource, Deref, DerefMut)]
 pub struct UpdateMovedPlayersTimer(pub Timer);
 
+/// Number of the last snapshot of the player movements sent to the clients.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ServerTick(pub u32);
+
+/// Sequence numbers of the movement updates received from each client. Used to drop updates which arrive
+/// late, twice or out of order, and to measure the packet loss of each client.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct MovementSequences(pub HashMap<u64, SequenceTracker>);
+
+/// Snapshots sent to a client which are not older than the latest one it acknowledged.
+#[derive(Default)]
+pub struct ClientSnapshots {
+    acked_tick: Option<u32>,
+    keyframe_tick: Option<u32>,
+    sent: VecDeque<(u32, PlayerStates)>,
+}
+
+impl ClientSnapshots {
+    /// Remembers that the client received the snapshot with the given tick. Older snapshots are no longer needed.
+    pub fn acknowledge(&mut self, tick: u32) {
+        if self.acked_tick.is_some_and(|acked_tick| acked_tick >= tick) {
+            return;
+        }
+        self.acked_tick = Some(tick);
+        self.sent.retain(|(sent_tick, _)| *sent_tick >= tick);
+    }
+
+    /// The movements sent with the latest snapshot.
+    pub fn last_sent(&self) -> Option<&PlayerStates> {
+        self.sent.back().map(|(_, states)| states)
+    }
+
+    /// Creates the next snapshot for the client. It contains the changes since the acknowledged snapshot,
+    /// or all players if this is a keyframe or the client did not acknowledge any of the kept snapshots.
+    pub fn next_snapshot(
+        &mut self,
+        tick: u32,
+        server_time: Duration,
+        current: PlayerStates,
+    ) -> MovedPlayersSnapshot {
+        let keyframe_due = match self.keyframe_tick {
+            Some(keyframe_tick) => tick - keyframe_tick >= KEYFRAME_INTERVAL,
+            None => true,
+        };
+        let baseline = match self.acked_tick {
+            Some(acked_tick) if !keyframe_due => self
+                .sent
+                .iter()
+                .find(|(sent_tick, _)| *sent_tick == acked_tick)
+                .map(|(sent_tick, states)| (*sent_tick, states)),
+            _ => None,
+        };
+        let snapshot = MovedPlayersSnapshot::new(tick, server_time, baseline, &current);
+        if snapshot.baseline_tick.is_none() {
+            self.keyframe_tick = Some(tick);
+        }
+
+        self.sent.push_back((tick, current));
+        while self.sent.len() > MAX_SENT_SNAPSHOTS {
+            self.sent.pop_front();
+        }
+        snapshot
+    }
+}
+
+/// The [`ClientSnapshots`] of each client.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct SentSnapshots(pub HashMap<u64, ClientSnapshots>);
+
+/// Players sorted into square cells as large as the interest radius, so the players near a position
+/// are found by only looking at the cells around it instead of at every player.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(u64, Vec2)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, position: Vec2) -> (i32, i32) {
+        let cell = (position / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    /// Adds the player with the given id at the given position.
+    pub fn insert(&mut self, id: u64, position: Vec2) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push((id, position));
+    }
+
+    /// Ids of all players whose distance to the position is at most the cell size.
+    pub fn near(&self, position: Vec2) -> impl Iterator<Item = u64> + '_ {
+        let (x, y) = self.cell(position);
+        (x - 1..=x + 1)
+            .flat_map(move |cell_x| (y - 1..=y + 1).map(move |cell_y| (cell_x, cell_y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |(_, other)| other.distance(position) <= self.cell_size)
+            .map(|(id, _)| *id)
+    }
+}
+
 //
 // ------> Events <------ //
 //
@@ -53,7 +220,18 @@ pub struct UpdateMovedPlayersTimer(pub Timer);
 #[derive(Event)]
 pub struct PlayerJoinedEvent {
     pub client_id: u64,
+    pub name: String,
     pub movement: PlayerMovement,
+    pub session_token: Option<String>,
+}
+
+/// Called when a client wants to watch the game as a spectator.
+#[derive(Event)]
+pub struct SpectatorJoinedEvent {
+    pub client_id: u64,
+    /// Name and session token of the client, used to check the [`Bans`].
+    pub name: String,
+    pub session_token: Option<String>,
 }
 
 /// Called when a player sends an update about his movement.
@@ -61,6 +239,8 @@ pub struct PlayerJoinedEvent {
 pub struct PlayerMovedEvent {
     pub client_id: u64,
     pub movement: PlayerMovement,
+    /// Tick of the newest snapshot the player received.
+    pub ack_tick: Option<u32>,
 }
 
 /// Called when a player left the game.
@@ -73,20 +253,107 @@ pub struct PlayerLeftEvent {
 // ------> Systems <------ //
 //
 
-/// Called when a player joines the game. Creates a new player entity with the given start position/movement.
+/// Called when a player joines the game. Creates a new player entity with the given name and start position/movement.
+/// If the name is invalid the join is rejected with a [`ServerMessage::JoinRejected`] message.
+///
+/// Players sending the token of a session which still exists resume it instead, as long as the client of the
+/// session is disconnected. The existing player entity is moved to the new client, so the player keeps the id,
+/// name, room and current run, and gets a new session token. Clients which already play or spectate are ignored.
+///
+/// Clients with a banned name or session, see [`Bans`], get a [`ServerMessage::Kicked`] message and are disconnected.
 pub fn on_player_joined(
+    time: Res<Time>,
     mut events: EventReader<PlayerJoinedEvent>,
     mut commands: Commands,
-    server: Res<Server>,
-    highscore: Res<HighscoreResource>,
+    mut server: ResMut<Server>,
+    highscores: Res<HighscoreResource>,
+    rooms: Res<Rooms>,
+    bans: Res<Bans>,
+    config: Res<ServerConfig>,
+    mut sessions: Query<(&mut Player, &mut Session, &mut InactiveTimer)>,
+    spectators: Query<&Spectator>,
 ) {
+    // The resumed and the new session both need most of the resources.
+    #![allow(clippy::too_many_arguments)]
     for ev in events.read() {
-        println!("Player {} joined the game.", ev.client_id);
+        let _span = info_span!("client", id = ev.client_id).entered();
+        if bans.is_banned(&ev.name, ev.session_token.as_deref()) {
+            warn!(name = %ev.name, "Refused banned player.");
+            refuse_banned(&mut server, ev.client_id);
+            continue;
+        }
+
+        let is_player = sessions.iter().any(|(p, _, _)| p.client_id == ev.client_id);
+        let is_spectator = spectators.iter().any(|s| s.client_id == ev.client_id);
+        if is_player || is_spectator {
+            continue;
+        }
+
+        // A session can only be resumed once its old client is gone, so a leaked token
+        // can not be used to take over the player of a connected client.
+        let connected_clients = server.endpoint().clients();
+        let resumed_session = ev.session_token.as_ref().and_then(|token| {
+            sessions.iter_mut().find(|(player, session, _)| {
+                &session.token == token && !connected_clients.contains(&player.client_id)
+            })
+        });
+        if let Some((mut player, mut session, mut inactive_timer)) = resumed_session {
+            info!(name = %player.name, player_id = player.id, "Player resumed the session.");
+            player.client_id = ev.client_id;
+            session.token = generate_session_token();
+            inactive_timer.0.reset();
+
+            server.endpoint().try_send_message(
+                ev.client_id,
+                ServerMessage::JoinAccepted(SessionInfo {
+                    player_id: player.id,
+                    session_token: session.token.clone(),
+                    resumed: true,
+                }),
+            );
+            rooms_system::send_room_to_player(
+                &server,
+                &rooms,
+                &highscores,
+                player.room_id,
+                ev.client_id,
+            );
+            continue;
+        }
+
+        let name = match validate_player_name(&ev.name) {
+            Ok(name) => name,
+            Err(reason) => {
+                warn!(%reason, "Rejected join of player.");
+                server
+                    .endpoint()
+                    .try_send_message(ev.client_id, ServerMessage::JoinRejected(reason));
+                continue;
+            }
+        };
+
+        info!(%name, player_id = ev.client_id, "Player joined the game.");
+
+        let session = Session {
+            token: generate_session_token(),
+        };
+        server.endpoint().try_send_message(
+            ev.client_id,
+            ServerMessage::JoinAccepted(SessionInfo {
+                player_id: ev.client_id,
+                session_token: session.token.clone(),
+                resumed: false,
+            }),
+        );
 
         commands.spawn((
             Player {
                 client_id: ev.client_id,
+                id: ev.client_id,
+                name,
+                room_id: LOBBY_ROOM_ID,
             },
+            session,
             Velocity {
                 x: ev.movement.velocity_x,
                 y: ev.movement.velocity_y,
@@ -95,27 +362,71 @@ pub fn on_player_joined(
                 x: ev.movement.translation_x,
                 y: ev.movement.translation_y,
             },
-            InactiveTimer(Timer::from_seconds(10.0, TimerMode::Once)),
+            InactiveTimer(Timer::new(config.inactive_timeout, TimerMode::Once)),
+            RunTracker::new(time.elapsed()),
+            ChatLimiter::new(time.elapsed()),
         ));
 
-        // Sends info about the current highscore to the player
-        server.endpoint().try_send_message(
-            ev.client_id,
-            ServerMessage::InformAboutHighscore(highscore.0.clone()),
-        );
+        // Sends info about the lobby and its current highscores to the player
+        rooms_system::send_room_to_player(&server, &rooms, &highscores, LOBBY_ROOM_ID, ev.client_id);
+    }
+}
+
+/// Called when a client wants to spectate. Creates a [`Spectator`] entity in the lobby and sends the lobby
+/// with its highscores to the client. Clients which already play or spectate are ignored, banned clients
+/// are refused like banned players.
+pub fn on_spectator_joined(
+    mut events: EventReader<SpectatorJoinedEvent>,
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    highscores: Res<HighscoreResource>,
+    rooms: Res<Rooms>,
+    bans: Res<Bans>,
+    members: RoomMembers,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        if bans.is_banned(&ev.name, ev.session_token.as_deref()) {
+            warn!(name = %ev.name, "Refused banned spectator.");
+            refuse_banned(&mut server, ev.client_id);
+            continue;
+        }
+
+        let is_player = members.players.iter().any(|p| p.client_id == ev.client_id);
+        let is_spectator = members.spectators.iter().any(|s| s.client_id == ev.client_id);
+        if is_player || is_spectator {
+            continue;
+        }
+
+        info!("Client is spectating.");
+        commands.spawn(Spectator {
+            client_id: ev.client_id,
+            name: ev.name.clone(),
+            session_token: ev.session_token.clone(),
+            room_id: LOBBY_ROOM_ID,
+        });
+        rooms_system::send_room_to_player(&server, &rooms, &highscores, LOBBY_ROOM_ID, ev.client_id);
     }
 }
 
-/// Called when a player send a update about his movement. Updates the values in the entity.
+/// Called when a player send a update about his movement. Updates the values in the entity and
+/// remembers which snapshot the player received last.
 /// Informing other clients about the updated movement happens in [`send_updates_to_players`].
 pub fn on_player_moved(
     mut events: EventReader<PlayerMovedEvent>,
+    mut sent_snapshots: ResMut<SentSnapshots>,
     mut players: Query<
         (&Player, &mut Velocity, &mut Translation, &mut InactiveTimer),
         With<Player>,
     >,
 ) {
     for ev in events.read() {
+        if let Some(ack_tick) = ev.ack_tick {
+            if let Some(client_snapshots) = sent_snapshots.get_mut(&ev.client_id) {
+                client_snapshots.acknowledge(ack_tick);
+            }
+        }
+
         for (player, mut velocity, mut translation, mut inactive_timer) in players.iter_mut() {
             if player.client_id == ev.client_id {
                 velocity.x = ev.movement.velocity_x;
@@ -130,16 +441,24 @@ pub fn on_player_moved(
     }
 }
 
-/// Called when a player left the game. Removes the player entity.
+/// Called when a player left the game. Removes the player or spectator entity.
 pub fn on_player_left(
     mut events: EventReader<PlayerLeftEvent>,
     mut commands: Commands,
     mut players: Query<(Entity, &Player), With<Player>>,
+    spectators: Query<(Entity, &Spectator)>,
 ) {
     for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
         for (entity, player) in players.iter_mut() {
             if player.client_id == ev.client_id {
-                println!("Player {} left the game.", player.client_id);
+                info!(name = %player.name, "Player left the game.");
+                commands.entity(entity).despawn();
+            }
+        }
+        for (entity, spectator) in spectators.iter() {
+            if spectator.client_id == ev.client_id {
+                info!("Spectator left the game.");
                 commands.entity(entity).despawn();
             }
         }
@@ -147,43 +466,167 @@ pub fn on_player_left(
 }
 
 /// Informs all players about the current movements of the other players. The updates are sent in a
-/// defined interval via the [`UpdateMovedPlayersTimer`]. Each update sent to a client contains
-/// all players movement excluded his own.
+/// defined interval via the [`UpdateMovedPlayersTimer`]. Each update sent to a client contains the
+/// movements of all players in the same room excluded his own. Clients which did not join yet get the lobby.
+///
+/// Only players within the interest radius of the [`ServerConfig`] are updated every time, found with a
+/// [`SpatialGrid`]. Players further away, and all players for clients which did not join yet, are updated
+/// every [`DISTANT_UPDATE_INTERVAL`] ticks. In between they keep the movement sent last. Spectators may
+/// follow any player, so they get every player of their room every time.
+///
+/// Every update is stamped with the next [`ServerTick`] and the time of the server. It is sent on the
+/// unreliable channel, as a lost update is replaced by the next one anyway. The movements are quantized
+/// and only the players which changed since the last snapshot acknowledged by the client are sent,
+/// see [`ClientSnapshots`]. The size of the sent updates is counted in the [`ServerMetrics`].
 pub fn send_updates_to_players(
     time: Res<Time>,
     mut timer: ResMut<UpdateMovedPlayersTimer>,
+    mut tick: ResMut<ServerTick>,
+    mut sent_snapshots: ResMut<SentSnapshots>,
+    mut ticks_until_distant_update: Local<u32>,
     server: Res<Server>,
+    config: Res<ServerConfig>,
     players: Query<(&Player, &Velocity, &Translation), With<Player>>,
+    spectators: Query<&Spectator>,
+    metrics: Res<ServerMetrics>,
 ) {
+    // Bevy systems get one argument per resource they use.
+    #![allow(clippy::too_many_arguments)]
     timer.tick(time.delta());
     if !timer.finished() {
         return;
     };
+    tick.0 += 1;
+
+    let distant_update = *ticks_until_distant_update == 0;
+    *ticks_until_distant_update = if distant_update {
+        DISTANT_UPDATE_INTERVAL - 1
+    } else {
+        *ticks_until_distant_update - 1
+    };
+
+    let mut grids: HashMap<u64, SpatialGrid> = HashMap::new();
+    let mut movements: HashMap<u64, PlayerStates> = HashMap::new();
+    let mut positions: HashMap<u64, (u64, u64, Vec2)> = HashMap::new();
+    for (player, velocity, translation) in players.iter() {
+        let movement = PlayerMovement {
+            velocity_x: velocity.x,
+            velocity_y: velocity.y,
+            translation_x: translation.x,
+            translation_y: translation.y,
+        };
+        let position = Vec2::new(translation.x, translation.y);
+        grids
+            .entry(player.room_id)
+            .or_insert_with(|| SpatialGrid::new(config.interest_radius))
+            .insert(player.id, position);
+        movements
+            .entry(player.room_id)
+            .or_default()
+            .insert(player.id, QuantizedMovement::from(&movement));
+        positions.insert(player.client_id, (player.id, player.room_id, position));
+    }
+
+    let spectator_rooms: HashMap<u64, u64> = spectators
+        .iter()
+        .map(|spectator| (spectator.client_id, spectator.room_id))
+        .collect();
 
     let endpoint = server.endpoint();
+    let no_movements = PlayerStates::new();
 
     for client_id in endpoint.clients() {
-        let mut players_movements: Vec<PlayerMovedUpdate> = Vec::new();
-
-        for (player, velocity, translation) in players.iter() {
-            if player.client_id != client_id {
-                let update = PlayerMovedUpdate {
-                    id: player.client_id,
-                    movement: PlayerMovement {
-                        velocity_x: velocity.x,
-                        velocity_y: velocity.y,
-                        translation_x: translation.x,
-                        translation_y: translation.y,
-                    },
-                };
-                players_movements.push(update);
+        let client_snapshots = sent_snapshots.entry(client_id).or_default();
+        let spectator_room = spectator_rooms.get(&client_id).copied();
+        let room_id = match positions.get(&client_id) {
+            Some((_, room_id, _)) => *room_id,
+            None => spectator_room.unwrap_or(LOBBY_ROOM_ID),
+        };
+        let room_movements = movements.get(&room_id).unwrap_or(&no_movements);
+
+        // Players who left the room are dropped from the movements sent last
+        let full_update = distant_update || spectator_room.is_some();
+        let mut players_movements = match (full_update, client_snapshots.last_sent()) {
+            (false, Some(last_sent)) => last_sent
+                .iter()
+                .filter(|(id, _)| room_movements.contains_key(id))
+                .map(|(id, movement)| (*id, *movement))
+                .collect(),
+            (false, None) => PlayerStates::new(),
+            (true, _) => room_movements.clone(),
+        };
+        if let Some((own_id, _, position)) = positions.get(&client_id) {
+            for id in grids[&room_id].near(*position) {
+                players_movements.insert(id, room_movements[&id]);
             }
+            players_movements.remove(own_id);
         }
 
-        endpoint.try_send_message(
-            client_id,
-            ServerMessage::UpdateMovedPlayers(players_movements),
-        );
+        let snapshot = client_snapshots.next_snapshot(tick.0, time.elapsed(), players_movements);
+        let message = ServerMessage::UpdateMovedPlayers(snapshot);
+        // Same serialization as bevy_quinnet uses to send the message
+        let bytes = bincode::serialized_size(&message).unwrap_or(0);
+        metrics.update_bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        endpoint.try_send_message_on(client_id, ChannelId::Unreliable, message);
+    }
+}
+
+/// Removes the [`MovementSequences`] and [`SentSnapshots`] of disconnected clients and logs how many of their
+/// updates got lost. Spectators have no session to resume, so their entity is removed as well.
+pub fn forget_disconnected_players(
+    mut events: EventReader<ConnectionLostEvent>,
+    mut commands: Commands,
+    mut sequences: ResMut<MovementSequences>,
+    mut sent_snapshots: ResMut<SentSnapshots>,
+    spectators: Query<(Entity, &Spectator)>,
+) {
+    for ev in events.read() {
+        sent_snapshots.remove(&ev.id);
+        for (entity, spectator) in spectators.iter() {
+            if spectator.client_id == ev.id {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Some(tracker) = sequences.remove(&ev.id) {
+            info!(
+                client_id = ev.id,
+                packet_loss = tracker.packet_loss(),
+                "Client lost {:.1}% of its movement updates.",
+                tracker.packet_loss() * 100.0
+            );
+        }
+    }
+}
+
+/// Informs all players about the names of the players in their room. The roster is sent whenever
+/// a player entity was spawned, removed, moved to a new client after reconnecting or entered another room,
+/// and whenever a spectator started watching or entered another room. Spectators are not part of the roster.
+/// Clients which did not join yet get the roster of the lobby.
+pub fn send_roster_on_change(
+    server: Res<Server>,
+    members: RoomMembers,
+    changed_players: Query<(), Changed<Player>>,
+    changed_spectators: Query<(), Changed<Spectator>>,
+    mut removed_players: RemovedComponents<Player>,
+) {
+    let removed = removed_players.read().count() > 0;
+    if changed_players.is_empty() && changed_spectators.is_empty() && !removed {
+        return;
+    }
+
+    let mut rosters: HashMap<u64, Vec<RosterEntry>> = HashMap::new();
+    for player in members.players.iter() {
+        rosters.entry(player.room_id).or_default().push(RosterEntry {
+            id: player.id,
+            name: player.name.clone(),
+        });
+    }
+
+    let endpoint = server.endpoint();
+    for client_id in endpoint.clients() {
+        let room_id = members.room_of(client_id);
+        let roster = rosters.get(&room_id).cloned().unwrap_or_default();
+        endpoint.try_send_message(client_id, ServerMessage::PlayerRoster(roster));
     }
 }
 
@@ -194,12 +637,14 @@ pub fn remove_inactive_players(
     mut server: ResMut<Server>,
     mut commands: Commands,
     mut players: Query<(Entity, &Player, &mut InactiveTimer), With<Player>>,
+    metrics: Res<ServerMetrics>,
 ) {
     for (entity, player, mut inactive_timer) in players.iter_mut() {
         inactive_timer.0.tick(time.delta());
 
         if inactive_timer.0.finished() {
-            println!("Removed player {} due to inactivity.", player.client_id);
+            info!(client_id = player.client_id, name = %player.name, "Removed player due to inactivity.");
+            metrics.inactive_removals.fetch_add(1, Ordering::Relaxed);
 
             commands.entity(entity).despawn();
             server
@@ -209,6 +654,44 @@ pub fn remove_inactive_players(
     }
 }
 
+/// Checks that a player name has between [`MIN_NAME_LENGTH`] and [`MAX_NAME_LENGTH`] characters
+/// and only contains letters, digits, spaces, `-` and `_`. Leading and trailing whitespace is ignored.
+/// Returns the trimmed name or the reason why it is invalid.
+pub fn validate_player_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    let length = name.chars().count();
+
+    if !(MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&length) {
+        return Err(format!(
+            "The name must have between {} and {} characters.",
+            MIN_NAME_LENGTH, MAX_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+    {
+        return Err("The name may only contain letters, digits, spaces, '-' and '_'.".to_string());
+    }
+
+    Ok(name.to_string())
+}
+
+/// Tells a banned client why it can not join and closes its connection.
+fn refuse_banned(server: &mut Server, client_id: u64) {
+    let endpoint = server.endpoint_mut();
+    endpoint.try_send_message(
+        client_id,
+        ServerMessage::Kicked("You are banned from this server.".to_string()),
+    );
+    endpoint.try_disconnect_client(client_id);
+}
+
+/// Creates a random token which can not be guessed by other players.
+fn generate_session_token() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
 //
 // ------> Tests <------ //
 //
@@ -220,7 +703,10 @@ fn _tests_util_create_server_app() -> App {
     use bevy::time::TimePlugin;
     use bevy_quinnet::server::QuinnetServerPlugin;
     let mut server_app = App::new();
-    server_app.add_plugins((QuinnetServerPlugin::default(), TimePlugin::default()));
+    server_app.add_plugins((QuinnetServerPlugin::default(), TimePlugin));
+    server_app.insert_resource(ServerConfig::default());
+    server_app.insert_resource(Bans::default());
+    server_app.insert_resource(ServerMetrics::default());
     server_app
 }
 
@@ -271,6 +757,7 @@ fn _tests_util_connect_client_to_server(server_app: &mut App, client_app: &mut A
             .unwrap()
             .connection()
             .is_connected()
+            && !server_app.world.resource::<Server>().endpoint().clients().is_empty()
         {
             break;
         }
@@ -278,10 +765,11 @@ fn _tests_util_connect_client_to_server(server_app: &mut App, client_app: &mut A
 }
 
 fn _tests_util_add_highscore_resource(server_app: &mut App) {
-    use shared::Highscore;
-    server_app.insert_resource(HighscoreResource(Highscore {
-        time_in_seconds: 0, // 0 means -> No highscore set yet.
-    }));
+    server_app.insert_resource(HighscoreResource::default());
+}
+
+fn _tests_util_add_rooms_resource(server_app: &mut App) {
+    server_app.insert_resource(Rooms::default());
 }
 
 #[test]
@@ -289,18 +777,21 @@ fn test_player_join() {
     let mut server_app = _tests_util_create_server_app();
     let mut client_app = _tests_util_create_client_app();
     _tests_util_add_highscore_resource(&mut server_app);
+    _tests_util_add_rooms_resource(&mut server_app);
     _tests_util_start_server(&mut server_app);
     _tests_util_connect_client_to_server(&mut server_app, &mut client_app);
 
     // Create event
     let player_joined_event = PlayerJoinedEvent {
         client_id: 1,
+        name: "Tester".to_string(),
         movement: PlayerMovement {
             velocity_x: 0.0,
             velocity_y: 0.0,
             translation_x: 0.0,
             translation_y: 0.0,
         },
+        session_token: None,
     };
     server_app.add_event::<PlayerJoinedEvent>();
     server_app.world.send_event(player_joined_event);
@@ -316,23 +807,132 @@ fn test_player_join() {
     assert_eq!(query.iter(&server_app.world).len(), 1);
 }
 
+#[test]
+fn test_player_resumes_session() {
+    let mut server_app = _tests_util_create_server_app();
+    let mut client_app = _tests_util_create_client_app();
+    _tests_util_add_highscore_resource(&mut server_app);
+    _tests_util_add_rooms_resource(&mut server_app);
+    _tests_util_start_server(&mut server_app);
+    _tests_util_connect_client_to_server(&mut server_app, &mut client_app);
+
+    let movement = PlayerMovement {
+        velocity_x: 0.0,
+        velocity_y: 0.0,
+        translation_x: 0.0,
+        translation_y: 0.0,
+    };
+    server_app.add_event::<PlayerJoinedEvent>();
+    server_app.add_systems(Update, on_player_joined);
+    // Client 7 is not connected, like a client which lost its connection
+    server_app.world.send_event(PlayerJoinedEvent {
+        client_id: 7,
+        name: "Tester".to_string(),
+        movement: movement.clone(),
+        session_token: None,
+    });
+    server_app.update();
+
+    // Reconnect as the connected client with the token of the session
+    let token = server_app
+        .world
+        .query::<&Session>()
+        .single(&server_app.world)
+        .token
+        .clone();
+    server_app.world.send_event(PlayerJoinedEvent {
+        client_id: 1,
+        name: "Other name".to_string(),
+        movement,
+        session_token: Some(token.clone()),
+    });
+    server_app.update();
+
+    // Test if the existing player was moved to the new client and got a new token
+    let mut query = server_app.world.query::<(&Player, &Session)>();
+    let players: Vec<(&Player, &Session)> = query.iter(&server_app.world).collect();
+    assert_eq!(players.len(), 1);
+    assert_eq!(players[0].0.client_id, 1);
+    assert_eq!(players[0].0.id, 7);
+    assert_eq!(players[0].0.name, "Tester");
+    assert_ne!(players[0].1.token, token);
+}
+
+#[test]
+fn test_session_of_connected_client_is_not_resumed() {
+    let mut server_app = _tests_util_create_server_app();
+    let mut client_app = _tests_util_create_client_app();
+    _tests_util_add_highscore_resource(&mut server_app);
+    _tests_util_add_rooms_resource(&mut server_app);
+    _tests_util_start_server(&mut server_app);
+    _tests_util_connect_client_to_server(&mut server_app, &mut client_app);
+
+    let movement = PlayerMovement {
+        velocity_x: 0.0,
+        velocity_y: 0.0,
+        translation_x: 0.0,
+        translation_y: 0.0,
+    };
+    server_app.add_event::<PlayerJoinedEvent>();
+    server_app.add_systems(Update, on_player_joined);
+    server_app.world.send_event(PlayerJoinedEvent {
+        client_id: 1,
+        name: "Tester".to_string(),
+        movement: movement.clone(),
+        session_token: None,
+    });
+    server_app.update();
+
+    // The same client joins again and another client uses the token of the connected client
+    let token = server_app
+        .world
+        .query::<&Session>()
+        .single(&server_app.world)
+        .token
+        .clone();
+    server_app.world.send_event(PlayerJoinedEvent {
+        client_id: 1,
+        name: "Tester".to_string(),
+        movement: movement.clone(),
+        session_token: None,
+    });
+    server_app.world.send_event(PlayerJoinedEvent {
+        client_id: 2,
+        name: "Other".to_string(),
+        movement,
+        session_token: Some(token),
+    });
+    server_app.update();
+
+    // Test if the player stays with its client and the other client got its own player
+    let mut query = server_app.world.query::<&Player>();
+    let mut players: Vec<&Player> = query.iter(&server_app.world).collect();
+    players.sort_by_key(|p| p.client_id);
+    assert_eq!(players.len(), 2);
+    assert_eq!((players[0].client_id, players[0].name.as_str()), (1, "Tester"));
+    assert_eq!((players[1].client_id, players[1].name.as_str()), (2, "Other"));
+}
+
 #[test]
 fn test_remove_inactive_player() {
     let mut server_app = _tests_util_create_server_app();
     let mut client_app = _tests_util_create_client_app();
     _tests_util_add_highscore_resource(&mut server_app);
+    _tests_util_add_rooms_resource(&mut server_app);
     _tests_util_start_server(&mut server_app);
     _tests_util_connect_client_to_server(&mut server_app, &mut client_app);
 
     // Create event
     let player_joined_event = PlayerJoinedEvent {
         client_id: 1,
+        name: "Tester".to_string(),
         movement: PlayerMovement {
             velocity_x: 0.0,
             velocity_y: 0.0,
             translation_x: 0.0,
             translation_y: 0.0,
         },
+        session_token: None,
     };
     server_app.add_event::<PlayerJoinedEvent>();
     server_app.world.send_event(player_joined_event);
@@ -352,3 +952,75 @@ fn test_remove_inactive_player() {
         .query::<((Entity, &Player), With<Player>)>();
     assert_eq!(query.iter(&server_app.world).len(), 0);
 }
+
+#[test]
+fn test_delta_snapshots_reduce_bytes_per_second() {
+    // 20 players of which only one is moving, for one second of snapshots at the default rate
+    let ticks = 50;
+    let players = 20;
+    let snapshot_at = |tick: u32| -> PlayerStates {
+        (0..players)
+            .map(|id| {
+                let translation_x = if id == 0 { tick as f32 * 3.0 } else { id as f32 * 16.0 };
+                let movement = PlayerMovement {
+                    velocity_x: if id == 0 { 150.0 } else { 0.0 },
+                    velocity_y: 0.0,
+                    translation_x,
+                    translation_y: 48.0,
+                };
+                (id, QuantizedMovement::from(&movement))
+            })
+            .collect()
+    };
+
+    // Previous format: every player with four f32s in every snapshot
+    let mut full_bytes = 0;
+    for tick in 1..=ticks {
+        let full: Vec<(u64, PlayerMovement)> = snapshot_at(tick)
+            .into_iter()
+            .map(|(id, movement)| (id, PlayerMovement::from(movement)))
+            .collect();
+        full_bytes += bincode::serialize(&(tick, Duration::ZERO, full)).unwrap().len();
+    }
+
+    // The client acknowledges every snapshot
+    let mut client_snapshots = ClientSnapshots::default();
+    let mut delta_bytes = 0;
+    for tick in 1..=ticks {
+        let snapshot = client_snapshots.next_snapshot(tick, Duration::ZERO, snapshot_at(tick));
+        assert!(snapshot.baseline_tick.is_none() || snapshot.changed.len() == 1);
+        delta_bytes += bincode::serialize(&ServerMessage::UpdateMovedPlayers(snapshot))
+            .unwrap()
+            .len();
+        client_snapshots.acknowledge(tick);
+    }
+
+    assert!(
+        delta_bytes * 4 < full_bytes,
+        "delta snapshots need {} bytes per second, full snapshots {}",
+        delta_bytes,
+        full_bytes
+    );
+}
+
+#[test]
+fn test_spatial_grid_finds_players_within_radius() {
+    let mut grid = SpatialGrid::new(100.0);
+    grid.insert(1, Vec2::new(0.0, 0.0));
+    grid.insert(2, Vec2::new(60.0, 70.0));
+    grid.insert(3, Vec2::new(150.0, 0.0));
+    grid.insert(4, Vec2::new(-80.0, -20.0));
+    grid.insert(5, Vec2::new(5000.0, 0.0));
+
+    let mut near: Vec<u64> = grid.near(Vec2::new(10.0, 10.0)).collect();
+    near.sort();
+    assert_eq!(near, vec![1, 2, 4]);
+}
+
+#[test]
+fn test_validate_player_name() {
+    assert_eq!(validate_player_name("  Speedy_1 "), Ok("Speedy_1".to_string()));
+    assert!(validate_player_name("ab").is_err());
+    assert!(validate_player_name("a name that is way too long").is_err());
+    assert!(validate_player_name("<script>").is_err());
+}
@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use shared::{RacePlacing, ServerMessage};
+
+use crate::highscore_system::RequestHighscoreEvent;
+use crate::players_system::Player;
+use crate::rooms_system::RoomMembers;
+
+/// Time between all players being ready and the start of the race. Long enough for the countdown
+/// to reach every player before the start.
+const RACE_COUNTDOWN: Duration = Duration::from_secs(3);
+
+/// How much earlier than the start of the race a run may start. The clocks of the players are synchronized
+/// with the server only up to their latency, so their runs start slightly before or after the start.
+const START_TOLERANCE: Duration = Duration::from_millis(250);
+
+/// Races in which not every player finished end after this time, so the room can start a new one.
+const MAX_RACE_DURATION: Duration = Duration::from_secs(600);
+
+//
+// ------> Resources <------ //
+//
+
+/// A race which was started in a room.
+struct RunningRace {
+    /// Time of the server at which the players may start moving.
+    start_at: Duration,
+    /// Ids of the players who were in the room when the countdown started.
+    racers: HashSet<u64>,
+    placings: Vec<RacePlacing>,
+}
+
+/// Race state of a single room.
+#[derive(Default)]
+pub struct Race {
+    /// Ids of the players who are ready for the next race.
+    ready: HashSet<u64>,
+    running: Option<RunningRace>,
+}
+
+impl Race {
+    /// Marks the player as ready or not ready. Returns the ids of all ready players, sorted.
+    fn set_ready(&mut self, player_id: u64, ready: bool) -> Vec<u64> {
+        if ready {
+            self.ready.insert(player_id);
+        } else {
+            self.ready.remove(&player_id);
+        }
+        let mut ready: Vec<u64> = self.ready.iter().copied().collect();
+        ready.sort();
+        ready
+    }
+
+    /// Starts the countdown if no race is running and all given players of the room are ready.
+    /// Returns the time of the server at which the race starts.
+    fn try_start(&mut self, now: Duration, players: &HashSet<u64>) -> Option<Duration> {
+        // Players who left the room are not waited for
+        self.ready.retain(|id| players.contains(id));
+        if self.running.is_some() || players.is_empty() || self.ready.len() < players.len() {
+            return None;
+        }
+
+        let start_at = now + RACE_COUNTDOWN;
+        self.ready.clear();
+        self.running = Some(RunningRace {
+            start_at,
+            racers: players.clone(),
+            placings: Vec::new(),
+        });
+        Some(start_at)
+    }
+
+    /// Adds the player to the finishing order of the running race. Returns the order if the player is one
+    /// of the racers, did not finish before and started the run at the start of the race. Runs started
+    /// during the countdown, e.g. by moving while the input should be locked, do not count.
+    fn finish(
+        &mut self,
+        now: Duration,
+        run_time: Duration,
+        player: &Player,
+    ) -> Option<&[RacePlacing]> {
+        let race = self.running.as_mut()?;
+        let already_finished = race.placings.iter().any(|p| p.player_id == player.id);
+        if now < race.start_at || !race.racers.contains(&player.id) || already_finished {
+            return None;
+        }
+        let run_started_at = now.saturating_sub(run_time);
+        if run_started_at + START_TOLERANCE < race.start_at {
+            warn!(name = %player.name, "Ignoring a run which started before the race.");
+            return None;
+        }
+
+        race.placings.push(RacePlacing {
+            player_id: player.id,
+            player_name: player.name.clone(),
+            time: now - race.start_at,
+        });
+        Some(&race.placings)
+    }
+
+    /// Ends the running race once every racer still in the room finished or it took too long.
+    fn end_if_over(&mut self, now: Duration, players: &HashSet<u64>) {
+        let Some(race) = &self.running else {
+            return;
+        };
+        let all_finished = race
+            .racers
+            .iter()
+            .filter(|id| players.contains(id))
+            .all(|id| race.placings.iter().any(|p| p.player_id == *id));
+
+        if all_finished || now > race.start_at + MAX_RACE_DURATION {
+            self.running = None;
+        }
+    }
+}
+
+/// The [`Race`] of each room, keyed by the id of the room.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Races(pub HashMap<u64, Race>);
+
+//
+// ------> Events <------ //
+//
+
+/// Called when a player readied up for the next race or took it back.
+#[derive(Event)]
+pub struct RaceReadyEvent {
+    pub client_id: u64,
+    pub ready: bool,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Updates which players are ready for the next race and informs the players in the same room.
+pub fn on_race_ready(
+    mut events: EventReader<RaceReadyEvent>,
+    mut races: ResMut<Races>,
+    server: Res<Server>,
+    members: RoomMembers,
+) {
+    for ev in events.read() {
+        let Some(player) = members.players.iter().find(|p| p.client_id == ev.client_id) else {
+            continue;
+        };
+
+        let ready = races
+            .entry(player.room_id)
+            .or_default()
+            .set_ready(player.id, ev.ready);
+        members.send_to_room(&server, player.room_id, ServerMessage::RaceReady(ready));
+    }
+}
+
+/// Starts a race with a countdown in every room in which all players are ready, see [`Race::try_start`],
+/// and ends the races which are over. The races of empty rooms are removed.
+pub fn update_races(
+    time: Res<Time>,
+    mut races: ResMut<Races>,
+    server: Res<Server>,
+    members: RoomMembers,
+) {
+    let mut rooms: HashMap<u64, HashSet<u64>> = HashMap::new();
+    for player in members.players.iter() {
+        rooms.entry(player.room_id).or_default().insert(player.id);
+    }
+    races.retain(|room_id, _| rooms.contains_key(room_id));
+
+    for (room_id, race) in races.iter_mut() {
+        let players_in_room = &rooms[room_id];
+        race.end_if_over(time.elapsed(), players_in_room);
+
+        if let Some(start_at) = race.try_start(time.elapsed(), players_in_room) {
+            info!(room_id, countdown = ?RACE_COUNTDOWN, "Race starts.");
+            members.send_to_room(&server, *room_id, ServerMessage::RaceReady(Vec::new()));
+            members.send_to_room(&server, *room_id, ServerMessage::RaceCountdown(start_at));
+        }
+    }
+}
+
+/// Adds every finished run of a racer to the finishing order of the race and announces the order to all
+/// players in the room. Only runs accepted by the [`crate::run_validation_system`] and started after the
+/// countdown count, the time is measured from the synchronized start of the race.
+pub fn on_race_finish(
+    time: Res<Time>,
+    mut events: EventReader<RequestHighscoreEvent>,
+    mut races: ResMut<Races>,
+    server: Res<Server>,
+    members: RoomMembers,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        let Some(player) = members.players.iter().find(|p| p.client_id == ev.client_id) else {
+            continue;
+        };
+        let Some(race) = races.get_mut(&player.room_id) else {
+            continue;
+        };
+
+        let run_time = ev.possible_highscore.time;
+        if let Some(placings) = race.finish(time.elapsed(), run_time, player) {
+            info!(
+                name = %player.name,
+                room_id = player.room_id,
+                placing = placings.len(),
+                "Player finished the race."
+            );
+            members.send_to_room(
+                &server,
+                player.room_id,
+                ServerMessage::RaceResults(placings.to_vec()),
+            );
+        }
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+fn _tests_util_player(id: u64) -> Player {
+    Player {
+        client_id: id,
+        id,
+        name: format!("Player {}", id),
+        room_id: 0,
+    }
+}
+
+#[test]
+fn test_race_starts_when_all_players_are_ready() {
+    let mut race = Race::default();
+    let players = HashSet::from([1, 2]);
+
+    assert_eq!(race.set_ready(1, true), vec![1]);
+    assert_eq!(race.try_start(Duration::ZERO, &players), None);
+
+    // A player who left the room is not waited for
+    race.set_ready(3, true);
+    assert_eq!(race.set_ready(2, true), vec![1, 2, 3]);
+    let start_at = race.try_start(Duration::from_secs(10), &players);
+    assert_eq!(start_at, Some(Duration::from_secs(10) + RACE_COUNTDOWN));
+    assert!(race.ready.is_empty());
+}
+
+#[test]
+fn test_race_announces_finishing_order() {
+    let mut race = Race::default();
+    let players = HashSet::from([1, 2]);
+    race.set_ready(1, true);
+    race.set_ready(2, true);
+    let start_at = race.try_start(Duration::ZERO, &players).unwrap();
+    let secs = Duration::from_secs;
+
+    // Finishing before the start does not count
+    assert!(race.finish(secs(1), secs(1), &_tests_util_player(2)).is_none());
+    assert!(race.finish(start_at + secs(40), secs(40), &_tests_util_player(3)).is_none());
+
+    let placings = race
+        .finish(start_at + secs(41), secs(41), &_tests_util_player(2))
+        .unwrap();
+    assert_eq!(placings.len(), 1);
+    assert_eq!(placings[0].time, Duration::from_secs(41));
+    assert!(race.finish(start_at + secs(42), secs(1), &_tests_util_player(2)).is_none());
+
+    race.end_if_over(start_at + Duration::from_secs(42), &players);
+    assert!(race.running.is_some());
+    let placings = race
+        .finish(start_at + secs(45), secs(45), &_tests_util_player(1))
+        .unwrap();
+    assert_eq!(placings[1].player_id, 1);
+    race.end_if_over(start_at + Duration::from_secs(45), &players);
+    assert!(race.running.is_none());
+}
+
+#[test]
+fn test_run_started_during_countdown_does_not_count() {
+    let mut race = Race::default();
+    let players = HashSet::from([1]);
+    race.set_ready(1, true);
+    let start_at = race.try_start(Duration::ZERO, &players).unwrap();
+    let finished_at = start_at + Duration::from_secs(2);
+
+    // The run started one second before the race
+    let early_run = Duration::from_secs(3);
+    assert!(race.finish(finished_at, early_run, &_tests_util_player(1)).is_none());
+
+    // The clock of the player may be slightly ahead of the server
+    let run = Duration::from_millis(2100);
+    assert!(race.finish(finished_at, run, &_tests_util_player(1)).is_some());
+}
@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use rand::Rng;
+use shared::{
+    CreateRoomRequest, Highscore, JoinRoomRequest, JoinedRoom, RoomInfo, ServerMessage,
+};
+
+use crate::highscore_system::{HighscoreResource, RequestHighscoreEvent};
+use crate::metrics_system::ServerMetrics;
+use crate::players_system::{Player, Spectator};
+
+/// Id of the lobby. Every player starts there and returns there when leaving a room.
+pub const LOBBY_ROOM_ID: u64 = 0;
+
+/// Name of the lobby shown in the list of rooms.
+const LOBBY_NAME: &str = "Lobby";
+
+/// Maximum number of rooms besides the lobby, so players can not fill the memory of the server.
+const MAX_ROOMS: usize = 100;
+
+/// Minimum number of characters of a room name.
+const MIN_ROOM_NAME_LENGTH: usize = 3;
+
+/// Maximum number of characters of a room name.
+const MAX_ROOM_NAME_LENGTH: usize = 24;
+
+/// Number of characters of a join code.
+const JOIN_CODE_LENGTH: usize = 6;
+
+/// Characters a join code is made of. Leaves out characters which are easily confused, like `0` and `O`.
+const JOIN_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+//
+// ------> Resources <------ //
+//
+
+/// A room created by a player. Players only see the ghosts and highscores of the players in the same room.
+pub struct Room {
+    pub name: String,
+    /// Code needed to join a private room. Public rooms have none.
+    pub join_code: Option<String>,
+    /// Best time of each level in this room, keyed by the LDtk level iid. Unlike the highscores
+    /// of the lobby they are not persisted and are gone with the room.
+    pub highscores: HashMap<String, Highscore>,
+}
+
+/// All rooms created by the players, keyed by their id. The lobby is not part of it, its highscores
+/// are the persisted ones of the [`HighscoreResource`].
+#[derive(Resource, Default)]
+pub struct Rooms {
+    last_id: u64,
+    rooms: HashMap<u64, Room>,
+}
+
+impl Rooms {
+    /// Creates a room and returns its id. Private rooms get a random join code.
+    pub fn create(&mut self, name: String, private: bool) -> Result<u64, String> {
+        if self.rooms.len() >= MAX_ROOMS {
+            return Err("There are too many rooms, try again later.".to_string());
+        }
+
+        self.last_id += 1;
+        self.rooms.insert(
+            self.last_id,
+            Room {
+                name,
+                join_code: private.then(generate_join_code),
+                highscores: HashMap::new(),
+            },
+        );
+        Ok(self.last_id)
+    }
+
+    pub fn get_mut(&mut self, room_id: u64) -> Option<&mut Room> {
+        self.rooms.get_mut(&room_id)
+    }
+
+    /// Checks if a player may join the room with the given join code. Join codes are not case sensitive.
+    pub fn check_join(&self, room_id: u64, join_code: Option<&str>) -> Result<(), String> {
+        if room_id == LOBBY_ROOM_ID {
+            return Ok(());
+        }
+        let Some(room) = self.rooms.get(&room_id) else {
+            return Err("The room does not exist anymore.".to_string());
+        };
+
+        match (&room.join_code, join_code) {
+            (None, _) => Ok(()),
+            (Some(expected), Some(given)) if expected.eq_ignore_ascii_case(given.trim()) => Ok(()),
+            (Some(_), _) => Err("Wrong join code for this private room.".to_string()),
+        }
+    }
+
+    /// The room as sent to a player who entered it.
+    pub fn joined_room(&self, room_id: u64) -> Option<JoinedRoom> {
+        if room_id == LOBBY_ROOM_ID {
+            return Some(JoinedRoom {
+                id: LOBBY_ROOM_ID,
+                name: LOBBY_NAME.to_string(),
+                join_code: None,
+            });
+        }
+        self.rooms.get(&room_id).map(|room| JoinedRoom {
+            id: room_id,
+            name: room.name.clone(),
+            join_code: room.join_code.clone(),
+        })
+    }
+
+    /// All rooms with the number of players in them, starting with the lobby. Join codes are not included.
+    pub fn list<'a>(&self, players: impl Iterator<Item = &'a Player>) -> Vec<RoomInfo> {
+        let mut counts: HashMap<u64, u32> = HashMap::new();
+        for player in players {
+            *counts.entry(player.room_id).or_default() += 1;
+        }
+        let count = |room_id: u64| counts.get(&room_id).copied().unwrap_or_default();
+
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .iter()
+            .map(|(id, room)| RoomInfo {
+                id: *id,
+                name: room.name.clone(),
+                players: count(*id),
+                private: room.join_code.is_some(),
+            })
+            .collect();
+        rooms.sort_by_key(|room| room.id);
+        rooms.insert(
+            0,
+            RoomInfo {
+                id: LOBBY_ROOM_ID,
+                name: LOBBY_NAME.to_string(),
+                players: count(LOBBY_ROOM_ID),
+                private: false,
+            },
+        );
+        rooms
+    }
+
+    /// Removes all rooms which are not in `occupied`. The lobby is never removed.
+    fn remove_empty(&mut self, occupied: &HashSet<u64>) {
+        self.rooms.retain(|room_id, _| occupied.contains(room_id));
+    }
+}
+
+/// The players and spectators of all rooms. Used to find out which clients are in a room.
+#[derive(SystemParam)]
+pub struct RoomMembers<'w, 's> {
+    pub players: Query<'w, 's, &'static Player>,
+    pub spectators: Query<'w, 's, &'static Spectator>,
+}
+
+impl<'w, 's> RoomMembers<'w, 's> {
+    /// Room of the given client. Clients which neither joined the game nor spectate are in the lobby.
+    pub fn room_of(&self, client_id: u64) -> u64 {
+        match self.spectators.iter().find(|s| s.client_id == client_id) {
+            Some(spectator) => spectator.room_id,
+            None => room_of(self.players.iter(), client_id),
+        }
+    }
+
+    /// Sends the message to every client in the given room, see [`RoomMembers::room_of`].
+    pub fn send_to_room(&self, server: &Server, room_id: u64, message: ServerMessage) {
+        let endpoint = server.endpoint();
+        for client_id in endpoint.clients() {
+            if self.room_of(client_id) == room_id {
+                endpoint.try_send_message(client_id, message.clone());
+            }
+        }
+    }
+}
+
+//
+// ------> Events <------ //
+//
+
+/// What a player wants to do with the rooms.
+pub enum RoomRequest {
+    Create(CreateRoomRequest),
+    List,
+    Join(JoinRoomRequest),
+    Leave,
+}
+
+/// Called when a player sent a message about the rooms.
+#[derive(Event)]
+pub struct RoomEvent {
+    pub client_id: u64,
+    pub request: RoomRequest,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Creates, lists, joins and leaves rooms as requested by the players. Players entering a room get a
+/// [`ServerMessage::RoomJoined`] message followed by the highscores of the room, requests which can not be
+/// fulfilled are answered with a [`ServerMessage::RoomRejected`] message. Only players who joined the game
+/// and spectators can enter a room, but spectators can not create one. The list of rooms is sent to every client.
+pub fn on_room_request(
+    mut events: EventReader<RoomEvent>,
+    mut rooms: ResMut<Rooms>,
+    highscores: Res<HighscoreResource>,
+    server: Res<Server>,
+    mut players: Query<&mut Player>,
+    mut spectators: Query<&mut Spectator>,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        if let RoomRequest::List = ev.request {
+            let list = rooms.list(players.iter());
+            server
+                .endpoint()
+                .try_send_message(ev.client_id, ServerMessage::RoomList(list));
+            continue;
+        }
+
+        let player = players.iter_mut().find(|p| p.client_id == ev.client_id);
+        let is_spectator = player.is_none();
+        let spectator = spectators.iter_mut().find(|s| s.client_id == ev.client_id);
+        let mut member_room = match (player, spectator) {
+            (Some(player), _) => player.map_unchanged(|player| &mut player.room_id),
+            (None, Some(spectator)) => spectator.map_unchanged(|spectator| &mut spectator.room_id),
+            (None, None) => {
+                reject(&server, ev.client_id, "Join the game before entering a room.".to_string());
+                continue;
+            }
+        };
+
+        let room_id = match &ev.request {
+            RoomRequest::List => continue,
+            RoomRequest::Create(_) if is_spectator => {
+                reject(&server, ev.client_id, "Spectators can not create rooms.".to_string());
+                continue;
+            }
+            RoomRequest::Create(request) => {
+                let created = validate_room_name(&request.name)
+                    .and_then(|name| rooms.create(name, request.private));
+                match created {
+                    Ok(room_id) => room_id,
+                    Err(reason) => {
+                        reject(&server, ev.client_id, reason);
+                        continue;
+                    }
+                }
+            }
+            RoomRequest::Join(request) => {
+                if let Err(reason) = rooms.check_join(request.room_id, request.join_code.as_deref()) {
+                    reject(&server, ev.client_id, reason);
+                    continue;
+                }
+                request.room_id
+            }
+            RoomRequest::Leave => LOBBY_ROOM_ID,
+        };
+
+        info!(room_id, "Client entered room.");
+        *member_room = room_id;
+        send_room_to_player(&server, &rooms, &highscores, room_id, ev.client_id);
+    }
+}
+
+/// Compares each requested time of a player in a room with the highscore of the room. New highscores are
+/// sent to all players and spectators in the room. Highscores of the lobby are handled by
+/// [`crate::highscore_system::on_request_highscore`].
+pub fn on_request_room_highscore(
+    mut events: EventReader<RequestHighscoreEvent>,
+    mut rooms: ResMut<Rooms>,
+    server: Res<Server>,
+    members: RoomMembers,
+    metrics: Res<ServerMetrics>,
+) {
+    for ev in events.read() {
+        let room_id = members.room_of(ev.client_id);
+        if room_id == LOBBY_ROOM_ID {
+            continue;
+        }
+        let Some(room) = rooms.get_mut(room_id) else {
+            continue;
+        };
+
+        let possible_highscore = &ev.possible_highscore;
+        let is_new_highscore = match room.highscores.get(&possible_highscore.level_iid) {
+            Some(highscore) => highscore.time > possible_highscore.time,
+            None => true,
+        };
+        if !is_new_highscore {
+            continue;
+        }
+
+        room.highscores
+            .insert(possible_highscore.level_iid.clone(), possible_highscore.clone());
+        metrics.highscore_changes.fetch_add(1, Ordering::Relaxed);
+        members.send_to_room(
+            &server,
+            room_id,
+            ServerMessage::InformAboutHighscore(possible_highscore.clone()),
+        );
+    }
+}
+
+/// Removes the rooms in which neither a player nor a spectator is left, including players who lost the
+/// connection but may still resume their session.
+pub fn remove_empty_rooms(mut rooms: ResMut<Rooms>, members: RoomMembers) {
+    let player_rooms = members.players.iter().map(|player| player.room_id);
+    let spectator_rooms = members.spectators.iter().map(|spectator| spectator.room_id);
+    let occupied: HashSet<u64> = player_rooms.chain(spectator_rooms).collect();
+    rooms.remove_empty(&occupied);
+}
+
+/// Room of the given client among the players. Clients which did not join the game yet are in the lobby.
+pub fn room_of<'a>(mut players: impl Iterator<Item = &'a Player>, client_id: u64) -> u64 {
+    players
+        .find(|player| player.client_id == client_id)
+        .map_or(LOBBY_ROOM_ID, |player| player.room_id)
+}
+
+/// Tells the client which room it is in and sends the highscores of the room.
+pub fn send_room_to_player(
+    server: &Server,
+    rooms: &Rooms,
+    highscores: &HighscoreResource,
+    room_id: u64,
+    client_id: u64,
+) {
+    let Some(joined_room) = rooms.joined_room(room_id) else {
+        return;
+    };
+    server
+        .endpoint()
+        .try_send_message(client_id, ServerMessage::RoomJoined(joined_room));
+
+    let room_highscores: Vec<Highscore> = match rooms.rooms.get(&room_id) {
+        Some(room) => room.highscores.values().cloned().collect(),
+        None => highscores
+            .values()
+            .map(|record| record.highscore.clone())
+            .collect(),
+    };
+    for highscore in room_highscores {
+        server
+            .endpoint()
+            .try_send_message(client_id, ServerMessage::InformAboutHighscore(highscore));
+    }
+}
+
+fn reject(server: &Server, client_id: u64, reason: String) {
+    info!(%reason, "Rejected room request.");
+    server
+        .endpoint()
+        .try_send_message(client_id, ServerMessage::RoomRejected(reason));
+}
+
+/// Checks that a room name has between [`MIN_ROOM_NAME_LENGTH`] and [`MAX_ROOM_NAME_LENGTH`] characters, only
+/// contains letters, digits, spaces, `-` and `_` and can not be mistaken for the lobby. Leading and trailing
+/// whitespace is ignored. Returns the trimmed name or the reason why it is invalid.
+pub fn validate_room_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    let length = name.chars().count();
+
+    if !(MIN_ROOM_NAME_LENGTH..=MAX_ROOM_NAME_LENGTH).contains(&length) {
+        return Err(format!(
+            "The room name must have between {} and {} characters.",
+            MIN_ROOM_NAME_LENGTH, MAX_ROOM_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+    {
+        return Err(
+            "The room name may only contain letters, digits, spaces, '-' and '_'.".to_string(),
+        );
+    }
+    if name.eq_ignore_ascii_case(LOBBY_NAME) {
+        return Err("The lobby already exists, choose another room name.".to_string());
+    }
+
+    Ok(name.to_string())
+}
+
+/// Creates a random join code which is short enough to be typed by the players.
+fn generate_join_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..JOIN_CODE_LENGTH)
+        .map(|_| JOIN_CODE_CHARS[rng.gen_range(0..JOIN_CODE_CHARS.len())] as char)
+        .collect()
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_private_room_needs_join_code() {
+    let mut rooms = Rooms::default();
+    let public = rooms.create("Open".to_string(), false).unwrap();
+    let private = rooms.create("Team A".to_string(), true).unwrap();
+    let join_code = rooms.joined_room(private).unwrap().join_code.unwrap();
+
+    assert_eq!(join_code.len(), JOIN_CODE_LENGTH);
+    assert!(rooms.check_join(public, None).is_ok());
+    assert!(rooms.check_join(LOBBY_ROOM_ID, None).is_ok());
+    assert!(rooms.check_join(private, None).is_err());
+    assert!(rooms.check_join(private, Some("WRONG1")).is_err());
+    assert!(rooms.check_join(private, Some(&join_code.to_lowercase())).is_ok());
+    assert!(rooms.check_join(private + 1, None).is_err());
+}
+
+#[test]
+fn test_list_rooms_and_remove_empty_ones() {
+    let mut rooms = Rooms::default();
+    let first = rooms.create("First".to_string(), true).unwrap();
+    let second = rooms.create("Second".to_string(), false).unwrap();
+    let players = [
+        Player {
+            client_id: 1,
+            id: 1,
+            name: "One".to_string(),
+            room_id: first,
+        },
+        Player {
+            client_id: 2,
+            id: 2,
+            name: "Two".to_string(),
+            room_id: LOBBY_ROOM_ID,
+        },
+    ];
+
+    let list = rooms.list(players.iter());
+    let ids: Vec<u64> = list.iter().map(|room| room.id).collect();
+    assert_eq!(ids, vec![LOBBY_ROOM_ID, first, second]);
+    assert_eq!(list[1].players, 1);
+    assert!(list[1].private);
+    assert_eq!(room_of(players.iter(), 1), first);
+    assert_eq!(room_of(players.iter(), 3), LOBBY_ROOM_ID);
+
+    rooms.remove_empty(&players.iter().map(|player| player.room_id).collect());
+    assert!(rooms.joined_room(first).is_some());
+    assert!(rooms.joined_room(second).is_none());
+    assert!(rooms.joined_room(LOBBY_ROOM_ID).is_some());
+}
+
+#[test]
+fn test_spectators_are_members_of_their_room() {
+    use bevy::ecs::system::SystemState;
+
+    let mut world = World::new();
+    world.spawn(Player {
+        client_id: 1,
+        id: 1,
+        name: "One".to_string(),
+        room_id: 4,
+    });
+    world.spawn(Spectator {
+        client_id: 2,
+        name: "Two".to_string(),
+        session_token: None,
+        room_id: 7,
+    });
+
+    let mut state: SystemState<RoomMembers> = SystemState::new(&mut world);
+    let members = state.get(&world);
+    assert_eq!(members.room_of(1), 4);
+    assert_eq!(members.room_of(2), 7);
+    assert_eq!(members.room_of(3), LOBBY_ROOM_ID);
+}
+
+#[test]
+fn test_validate_room_name() {
+    assert_eq!(validate_room_name(" Team A "), Ok("Team A".to_string()));
+    assert_eq!(
+        validate_room_name("Friday Night Speedruns"),
+        Ok("Friday Night Speedruns".to_string())
+    );
+    assert!(validate_room_name("ab").is_err());
+    assert!(validate_room_name("a room name that is way too long").is_err());
+    assert!(validate_room_name("<script>").is_err());
+    assert!(validate_room_name("lobby").is_err());
+}
@@ -0,0 +1,433 @@
+use std::{collections::HashSet, time::Duration};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use shared::{Highscore, ServerMessage, SPAWN_POSITION};
+
+use crate::{
+    highscore_system::RequestHighscoreEvent,
+    players_system::{Player, PlayerMovedEvent},
+};
+
+/// Maximum horizontal speed a player can reach. The game moves players with 100 pixels per second,
+/// the rest is tolerance for collisions pushing the player.
+const MAX_VELOCITY_X: f32 = 150.0;
+
+/// Maximum vertical speed a player can reach. Jumps start with 300 pixels per second,
+/// falling from the top of a level ends below 600 pixels per second.
+const MAX_VELOCITY_Y: f32 = 800.0;
+
+/// Maximum distance a player can travel between two movement updates, in pixels per second.
+const MAX_DISTANCE_PER_SECOND: f32 = 850.0;
+
+/// Minimum time span used to check the distance between two movement updates. Updates are sent every
+/// 20ms but network jitter can make them arrive in bursts, so shorter intervals are not meaningful.
+const MIN_DISTANCE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Distance in pixels a player is allowed to move in addition to [`MAX_DISTANCE_PER_SECOND`].
+const DISTANCE_TOLERANCE: f32 = 16.0;
+
+/// Distance in pixels around a spawn point in which a player counts as being at the spawn point.
+/// Runs have to start there, and a jump to a spawn point starts a new run, see [`RunTracker::track_movement`].
+const SPAWN_RADIUS: f32 = 12.0;
+
+/// Distance in pixels around the finish area in which a player counts as having reached it.
+const FINISH_MARGIN: f32 = 24.0;
+
+/// Spawn point and finish area of a level, in the same coordinates the players send their translation in.
+struct LevelArea {
+    iid: &'static str,
+    spawn: Vec2,
+    finish_min: Vec2,
+    finish_max: Vec2,
+}
+
+/// All levels of `jump_n_run.ldtk`. Has to be updated when levels are added or their
+/// spawn point or finish line move, which `test_levels_match_level_file` checks.
+const LEVELS: [LevelArea; 1] = [LevelArea {
+    iid: "106dbb80-d7b0-11ee-a935-958c545a1bce",
+    spawn: Vec2::from_array(SPAWN_POSITION),
+    finish_min: Vec2::new(48.0, 192.0),
+    finish_max: Vec2::new(64.0, 208.0),
+}];
+
+//
+// ------> Components <------ //
+//
+
+/// What the server observed about a single run of a player.
+struct ObservedRun {
+    started_at: Duration,
+    /// The first position of the player tracked during the run.
+    start_position: Option<Vec2>,
+    /// Iids of the levels whose finish area the player came near during the run.
+    reached_finish: HashSet<&'static str>,
+    /// The first impossible movement during the run.
+    violation: Option<String>,
+}
+
+impl ObservedRun {
+    fn new(started_at: Duration) -> Self {
+        ObservedRun {
+            started_at,
+            start_position: None,
+            reached_finish: HashSet::new(),
+            violation: None,
+        }
+    }
+}
+
+/// Measures the runs of a player on the server. Runs are started and finished by the
+/// `RunStarted` and `RunFinished` messages of the client, the movement updates in between
+/// are checked so only runs that really reached the finish are accepted.
+#[derive(Component)]
+pub struct RunTracker {
+    current: Option<ObservedRun>,
+    last_position: Option<Vec2>,
+    last_update: Duration,
+}
+
+impl RunTracker {
+    /// Creates the tracker for a player who just joined. The first run starts with joining the game.
+    pub fn new(now: Duration) -> Self {
+        RunTracker {
+            current: Some(ObservedRun::new(now)),
+            last_position: None,
+            last_update: now,
+        }
+    }
+
+    /// Starts a new run, discarding the current one. Called at the start of the level and
+    /// whenever the player is reset to the spawn point by a trap or by finishing.
+    fn start_run(&mut self, now: Duration) {
+        self.current = Some(ObservedRun::new(now));
+    }
+
+    /// Updates the current run with a movement update. A jump that is too far or a velocity that is
+    /// too high is recorded as violation. A jump to a spawn point is a reset by a trap or by finishing
+    /// and starts a new run, so jumping back from there is a violation of the new run.
+    fn track_movement(&mut self, now: Duration, position: Vec2, velocity: Vec2) {
+        if let Some(last_position) = self.last_position {
+            let at_spawn = LEVELS
+                .iter()
+                .any(|level| level.spawn.distance(position) <= SPAWN_RADIUS);
+            let distance = last_position.distance(position);
+            let interval = (now - self.last_update).max(MIN_DISTANCE_INTERVAL);
+            let max_distance = MAX_DISTANCE_PER_SECOND * interval.as_secs_f32() + DISTANCE_TOLERANCE;
+
+            if distance > max_distance {
+                if at_spawn {
+                    // Keep the start time of a run which was just started by the player
+                    let just_started = self
+                        .current
+                        .as_ref()
+                        .is_some_and(|run| run.start_position.is_none());
+                    if !just_started {
+                        self.start_run(now);
+                    }
+                } else {
+                    self.record_violation(format!(
+                        "moved {:.0} pixels in {:.3} seconds",
+                        distance,
+                        (now - self.last_update).as_secs_f32()
+                    ));
+                }
+            }
+        }
+
+        if velocity.x.abs() > MAX_VELOCITY_X || velocity.y.abs() > MAX_VELOCITY_Y {
+            self.record_violation(format!(
+                "impossible velocity of ({:.0}, {:.0})",
+                velocity.x, velocity.y
+            ));
+        }
+
+        if let Some(run) = self.current.as_mut() {
+            run.start_position.get_or_insert(position);
+            for level in LEVELS.iter() {
+                let near_finish = position.cmpge(level.finish_min - FINISH_MARGIN).all()
+                    && position.cmple(level.finish_max + FINISH_MARGIN).all();
+                if near_finish {
+                    run.reached_finish.insert(level.iid);
+                }
+            }
+        }
+
+        self.last_position = Some(position);
+        self.last_update = now;
+    }
+
+    fn record_violation(&mut self, violation: String) {
+        if let Some(run) = self.current.as_mut() {
+            if run.violation.is_none() {
+                run.violation = Some(violation);
+            }
+        }
+    }
+
+    /// Ends the current run on the level of the claim. Returns the run time measured by the server,
+    /// or the reason why the run is rejected.
+    fn finish_run(&mut self, now: Duration, claim: &Highscore) -> Result<Duration, String> {
+        let run = self
+            .current
+            .take()
+            .ok_or_else(|| "no run was started".to_string())?;
+        let level = LEVELS
+            .iter()
+            .find(|level| level.iid == claim.level_iid)
+            .ok_or_else(|| format!("unknown level {}", claim.level_iid))?;
+
+        let started_at_spawn = run
+            .start_position
+            .is_some_and(|position| level.spawn.distance(position) <= SPAWN_RADIUS);
+        if !started_at_spawn {
+            return Err("the run did not start at the spawn point".to_string());
+        }
+        if !run.reached_finish.contains(level.iid) {
+            return Err("the player never passed near the finish area".to_string());
+        }
+        if let Some(violation) = run.violation {
+            return Err(violation);
+        }
+
+        Ok(now - run.started_at)
+    }
+}
+
+//
+// ------> Events <------ //
+//
+
+/// Messages of a player about the current run. Both kinds are sent as one event
+/// so they are handled in the order the player sent them.
+pub enum RunMessage {
+    Started,
+    /// Contains the level and the time measured by the client.
+    Finished(Highscore),
+}
+
+/// Called when a player started or finished a run.
+#[derive(Event)]
+pub struct RunEvent {
+    pub client_id: u64,
+    pub message: RunMessage,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Updates the [`RunTracker`] of each player with the received movement updates.
+pub fn track_runs(
+    time: Res<Time>,
+    mut events: EventReader<PlayerMovedEvent>,
+    mut players: Query<(&Player, &mut RunTracker)>,
+) {
+    for ev in events.read() {
+        if let Some((_, mut tracker)) = players
+            .iter_mut()
+            .find(|(player, _)| player.client_id == ev.client_id)
+        {
+            tracker.track_movement(
+                time.elapsed(),
+                Vec2::new(ev.movement.translation_x, ev.movement.translation_y),
+                Vec2::new(ev.movement.velocity_x, ev.movement.velocity_y),
+            );
+        }
+    }
+}
+
+/// Starts and finishes the runs in the [`RunTracker`] of each player. The time of a finished run is
+/// measured by the server, sent back to the player with a [`ServerMessage::RunTimeConfirmed`] message and
+/// forwarded as [`RequestHighscoreEvent`]. Rejected runs are logged with the reason.
+pub fn on_run_event(
+    time: Res<Time>,
+    server: Res<Server>,
+    mut events: EventReader<RunEvent>,
+    mut ev_highscore_request: EventWriter<RequestHighscoreEvent>,
+    mut players: Query<(&Player, &mut RunTracker)>,
+) {
+    for ev in events.read() {
+        let _span = info_span!("client", id = ev.client_id).entered();
+        let Some((_, mut tracker)) = players
+            .iter_mut()
+            .find(|(player, _)| player.client_id == ev.client_id)
+        else {
+            continue;
+        };
+
+        let claim = match &ev.message {
+            RunMessage::Started => {
+                tracker.start_run(time.elapsed());
+                continue;
+            }
+            RunMessage::Finished(claim) => claim,
+        };
+
+        match tracker.finish_run(time.elapsed(), claim) {
+            Ok(official_time) => {
+                info!(
+                    level_iid = %claim.level_iid,
+                    time = official_time.as_secs_f64(),
+                    client_time = claim.time.as_secs_f64(),
+                    "Player finished a level."
+                );
+
+                let official_highscore = Highscore {
+                    level_iid: claim.level_iid.clone(),
+                    time: official_time,
+                };
+                server.endpoint().try_send_message(
+                    ev.client_id,
+                    ServerMessage::RunTimeConfirmed(official_highscore.clone()),
+                );
+                ev_highscore_request.send(RequestHighscoreEvent {
+                    client_id: ev.client_id,
+                    possible_highscore: official_highscore,
+                });
+            }
+            Err(reason) => warn!(
+                level_iid = %claim.level_iid,
+                client_time = claim.time.as_secs_f64(),
+                %reason,
+                "Rejected finish of player."
+            ),
+        }
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+fn _tests_util_claim() -> Highscore {
+    Highscore {
+        level_iid: LEVELS[0].iid.to_string(),
+        time: Duration::from_secs(1),
+    }
+}
+
+/// Walks a player from the spawn point up to the finish line, one update every 20ms.
+fn _tests_util_run_to_finish(tracker: &mut RunTracker, start: Duration) -> Duration {
+    let from = LEVELS[0].spawn;
+    let to = (LEVELS[0].finish_min + LEVELS[0].finish_max) / 2.0;
+    let steps = 200;
+    for step in 0..=steps {
+        let now = start + Duration::from_millis(20 * step);
+        let position = from.lerp(to, step as f32 / steps as f32);
+        tracker.track_movement(now, position, Vec2::new(0.0, 50.0));
+    }
+    start + Duration::from_millis(20 * steps)
+}
+
+#[test]
+fn test_run_time_is_measured_by_server() {
+    let mut tracker = RunTracker::new(Duration::ZERO);
+    tracker.start_run(Duration::from_secs(5));
+    let now = _tests_util_run_to_finish(&mut tracker, Duration::from_secs(5));
+
+    // The claimed time of one second is ignored
+    let official_time = tracker.finish_run(now, &_tests_util_claim()).unwrap();
+    assert_eq!(official_time, Duration::from_secs(4));
+    assert!(tracker.finish_run(now, &_tests_util_claim()).is_err());
+}
+
+#[test]
+fn test_run_without_finish_is_rejected() {
+    let mut tracker = RunTracker::new(Duration::ZERO);
+    tracker.track_movement(Duration::from_secs(30), LEVELS[0].spawn, Vec2::ZERO);
+
+    assert!(tracker
+        .finish_run(Duration::from_secs(60), &_tests_util_claim())
+        .is_err());
+}
+
+#[test]
+fn test_teleport_is_rejected() {
+    let mut tracker = RunTracker::new(Duration::ZERO);
+    tracker.track_movement(Duration::ZERO, LEVELS[0].spawn, Vec2::ZERO);
+    tracker.track_movement(Duration::from_millis(20), LEVELS[0].finish_min, Vec2::ZERO);
+
+    assert!(tracker
+        .finish_run(Duration::from_secs(10), &_tests_util_claim())
+        .is_err());
+}
+
+#[test]
+fn test_run_started_next_to_finish_is_rejected() {
+    let finish = LEVELS[0].finish_min;
+    let mut tracker = RunTracker::new(Duration::ZERO);
+    tracker.track_movement(Duration::ZERO, LEVELS[0].spawn, Vec2::ZERO);
+    _tests_util_run_to_finish(&mut tracker, Duration::ZERO);
+
+    // The run is restarted while standing in the finish area and finished right away
+    tracker.start_run(Duration::from_secs(10));
+    tracker.track_movement(Duration::from_secs(10), finish, Vec2::ZERO);
+    tracker.track_movement(Duration::from_millis(10_020), finish, Vec2::ZERO);
+
+    assert!(tracker
+        .finish_run(Duration::from_millis(10_040), &_tests_util_claim())
+        .is_err());
+}
+
+#[test]
+fn test_jump_to_spawn_and_back_is_rejected() {
+    let finish = LEVELS[0].finish_min;
+    let mut tracker = RunTracker::new(Duration::ZERO);
+    let now = _tests_util_run_to_finish(&mut tracker, Duration::ZERO);
+
+    // Jumping to the spawn point starts a new run, jumping back is a teleport within that run
+    tracker.track_movement(now + Duration::from_millis(20), LEVELS[0].spawn, Vec2::ZERO);
+    tracker.track_movement(now + Duration::from_millis(40), finish, Vec2::ZERO);
+
+    assert!(tracker
+        .finish_run(now + Duration::from_millis(60), &_tests_util_claim())
+        .is_err());
+}
+
+#[test]
+fn test_levels_match_level_file() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/assets/jump_n_run.ldtk");
+    let project: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let levels = project["levels"].as_array().unwrap();
+    assert_eq!(levels.len(), LEVELS.len());
+
+    for level in levels {
+        let iid = level["iid"].as_str().unwrap();
+        let area = LEVELS
+            .iter()
+            .find(|area| area.iid == iid)
+            .expect("level is missing in LEVELS");
+        // The level file counts y downwards from the top, the game upwards from the bottom
+        let height = level["pxHei"].as_f64().unwrap() as f32;
+
+        for layer in level["layerInstances"].as_array().unwrap() {
+            match layer["__identifier"].as_str().unwrap() {
+                "Player" => {
+                    let px = &layer["entityInstances"][0]["px"];
+                    let x = px[0].as_f64().unwrap() as f32;
+                    let y = height - px[1].as_f64().unwrap() as f32;
+                    assert_eq!(Vec2::new(x, y), area.spawn);
+                }
+                "Finish_Line_IntGrid" => {
+                    let columns = layer["__cWid"].as_u64().unwrap() as usize;
+                    let grid_size = layer["__gridSize"].as_f64().unwrap() as f32;
+                    let mut finish_min = Vec2::splat(f32::MAX);
+                    let mut finish_max = Vec2::splat(f32::MIN);
+                    for (i, value) in layer["intGridCsv"].as_array().unwrap().iter().enumerate() {
+                        if value.as_u64() == Some(0) {
+                            continue;
+                        }
+                        let x = (i % columns) as f32 * grid_size;
+                        let y = height - (i / columns) as f32 * grid_size;
+                        finish_min = finish_min.min(Vec2::new(x, y - grid_size));
+                        finish_max = finish_max.max(Vec2::new(x + grid_size, y));
+                    }
+                    assert_eq!((finish_min, finish_max), (area.finish_min, area.finish_max));
+                }
+                _ => {}
+            }
+        }
+    }
+}
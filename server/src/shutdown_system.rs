@@ -0,0 +1,145 @@
+use std::{
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_quinnet::server::Server;
+use shared::{ServerMessage, ShutdownInfo};
+
+use crate::admin_system::{self, BanStorage, Bans};
+use crate::config::ServerConfig;
+use crate::highscore_system::{self, HighscoreResource, HighscoreStorage};
+use crate::leaderboard_system::{self, LeaderboardResource, LeaderboardStorage};
+
+/// Time between telling the clients about the shutdown and closing their connections, so the
+/// [`ServerMessage::ServerShuttingDown`] message reaches them before the connection is gone.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Reason sent to the players when the server was stopped by a signal, e.g. by `docker stop`.
+const SIGNAL_REASON: &str = "The server is shutting down.";
+
+//
+// ------> Resources <------ //
+//
+
+/// Set when the process received SIGTERM or SIGINT (Ctrl+C).
+#[derive(Resource, Clone, Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+impl ShutdownSignal {
+    /// Installs the handler for SIGTERM and SIGINT. If that fails the server still runs, but is killed
+    /// by the signals without shutting down gracefully.
+    pub fn listen() -> Self {
+        let signal = ShutdownSignal::default();
+        let received = signal.0.clone();
+        if let Err(error) = ctrlc::set_handler(move || received.store(true, Ordering::SeqCst)) {
+            error!(%error, "Failed to listen for shutdown signals.");
+        }
+        signal
+    }
+}
+
+/// Exit status of the server. Shared with `main`, as the app is gone once it stopped running.
+#[derive(Resource, Clone, Default)]
+pub struct ExitStatus(Arc<AtomicBool>);
+
+impl ExitStatus {
+    /// Makes the server exit with a failure, e.g. because its state could not be saved.
+    pub fn fail(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn code(&self) -> ExitCode {
+        if self.0.load(Ordering::SeqCst) {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+//
+// ------> Events <------ //
+//
+
+/// Called when the server should stop, by a signal or the admin console.
+#[derive(Event)]
+pub struct ShutdownEvent {
+    pub reason: String,
+}
+
+//
+// ------> Systems <------ //
+//
+
+/// Fires a [`ShutdownEvent`] once the process received a [`ShutdownSignal`]. A second signal while
+/// shutting down is ignored, the shutdown takes less than a second anyway.
+pub fn check_shutdown_signal(signal: Res<ShutdownSignal>, mut events: EventWriter<ShutdownEvent>) {
+    if signal.0.swap(false, Ordering::SeqCst) {
+        info!("Received shutdown signal.");
+        events.send(ShutdownEvent {
+            reason: SIGNAL_REASON.to_string(),
+        });
+    }
+}
+
+/// Shuts the server down gracefully. Tells all clients why and when the server is expected back, see
+/// [`ServerConfig::restart_eta`], and saves the highscores, leaderboards and bans. After the
+/// [`SHUTDOWN_GRACE_PERIOD`] the clients are disconnected and the app exits. If any state could not be saved
+/// the server exits with a failure.
+pub fn on_shutdown(
+    time: Res<Time>,
+    mut events: EventReader<ShutdownEvent>,
+    mut grace_period: Local<Option<Timer>>,
+    mut server: ResMut<Server>,
+    config: Res<ServerConfig>,
+    exit_status: Res<ExitStatus>,
+    highscores: Res<HighscoreResource>,
+    highscore_storage: Res<HighscoreStorage>,
+    leaderboards: Res<LeaderboardResource>,
+    leaderboard_storage: Res<LeaderboardStorage>,
+    bans: Res<Bans>,
+    ban_storage: Res<BanStorage>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    // Flushing all state needs every storage.
+    #![allow(clippy::too_many_arguments)]
+    if let Some(ev) = events.read().last() {
+        if grace_period.is_none() {
+            info!(reason = %ev.reason, "Shutting down.");
+            server
+                .endpoint()
+                .try_broadcast_message(ServerMessage::ServerShuttingDown(ShutdownInfo {
+                    reason: ev.reason.clone(),
+                    restart_in: config.restart_eta,
+                }));
+
+            let saved = [
+                highscore_system::save_highscores(&highscore_storage, &highscores),
+                leaderboard_system::save_leaderboards(&leaderboard_storage, &leaderboards),
+                admin_system::save_bans(&ban_storage, &bans),
+            ];
+            if saved.contains(&false) {
+                exit_status.fail();
+            }
+            *grace_period = Some(Timer::new(SHUTDOWN_GRACE_PERIOD, TimerMode::Once));
+        }
+    }
+
+    let Some(timer) = grace_period.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    if let Err(error) = server.endpoint_mut().disconnect_all_clients() {
+        error!(%error, "Failed to disconnect the clients.");
+    }
+    info!("Server stopped.");
+    app_exit_events.send(AppExit);
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Reads and deserializes the JSON file at `path`. Returns `Ok(None)` if the file does not exist yet,
+/// which is the case on the very first start of the server.
+pub fn load<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Serializes `value` as JSON and writes it to `path`.
+///
+/// The data is first written and synced to a temporary file next to the target, which is then renamed
+/// over the target. A rename is atomic on the same filesystem, so a crash mid-write leaves either the
+/// old or the new file behind but never a partially written one.
+pub fn save_atomic<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let content = serde_json::to_vec_pretty(value)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let tmp_path = tmp_path_for(path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Returns the current time as seconds since the unix epoch. Used to timestamp stored records.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir()
+        .join(format!("jumpnrun_storage_test_{}", std::process::id()))
+        .join("value.json");
+
+    save_atomic(&path, &vec![1u64, 2, 3]).unwrap();
+    let loaded: Option<Vec<u64>> = load(&path).unwrap();
+
+    assert_eq!(loaded, Some(vec![1, 2, 3]));
+    assert!(!tmp_path_for(&path).exists());
+
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[test]
+fn test_load_missing_file() {
+    let path = std::env::temp_dir().join("jumpnrun_storage_test_missing.json");
+    let loaded: Option<u64> = load(&path).unwrap();
+    assert_eq!(loaded, None);
+}
@@ -1,5 +1,14 @@
+use std::{collections::BTreeMap, time::Duration};
+
 use serde::{Deserialize, Serialize};
 
+pub mod logging;
+
+/// Position in pixels at which every run starts, in the same coordinates as the translation of a [`PlayerMovement`].
+/// Traps, finishing and the start of a race put the player back there, and the server only accepts runs which
+/// start there. Has to match the spawn point of the levels in `jump_n_run.ldtk`.
+pub const SPAWN_POSITION: [f32; 2] = [40.0, 40.0];
+
 /// Stores the velocity and the translation of a player.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerMovement {
@@ -17,27 +26,453 @@ pub struct PlayerMovedUpdate {
     pub movement: PlayerMovement,
 }
 
-/// The current best time for the level. If `time_in_seconds` is zero
-/// there is no highscore yet.
+/// Movement of the player sent to the server. Numbered, so the server can drop updates which arrive
+/// late, twice or out of order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovementUpdate {
+    /// Increased by one for every update sent by the player.
+    pub sequence: u32,
+    /// Tick of the newest snapshot the game received. The server only sends the changes since this snapshot.
+    pub ack_tick: Option<u32>,
+    pub movement: PlayerMovement,
+}
+
+/// Number of steps per pixel used by [`QuantizedMovement`].
+const QUANTIZATION_STEPS_PER_PIXEL: f32 = 4.0;
+
+/// Movement of a player rounded to a quarter pixel, which needs half the bytes of a [`PlayerMovement`].
+/// Translations and velocities up to 8191 pixels (per second) in each direction can be stored,
+/// so levels have to fit into this range, which `test_quantized_movement_covers_levels` checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedMovement {
+    pub velocity_x: i16,
+    pub velocity_y: i16,
+    pub translation_x: i16,
+    pub translation_y: i16,
+}
+
+impl From<&PlayerMovement> for QuantizedMovement {
+    fn from(movement: &PlayerMovement) -> Self {
+        // Casting saturates at the bounds of i16
+        let quantize = |value: f32| (value * QUANTIZATION_STEPS_PER_PIXEL).round() as i16;
+        QuantizedMovement {
+            velocity_x: quantize(movement.velocity_x),
+            velocity_y: quantize(movement.velocity_y),
+            translation_x: quantize(movement.translation_x),
+            translation_y: quantize(movement.translation_y),
+        }
+    }
+}
+
+impl From<QuantizedMovement> for PlayerMovement {
+    fn from(movement: QuantizedMovement) -> Self {
+        let restore = |value: i16| value as f32 / QUANTIZATION_STEPS_PER_PIXEL;
+        PlayerMovement {
+            velocity_x: restore(movement.velocity_x),
+            velocity_y: restore(movement.velocity_y),
+            translation_x: restore(movement.translation_x),
+            translation_y: restore(movement.translation_y),
+        }
+    }
+}
+
+/// Movements of all players in a snapshot, keyed by the id of the player.
+pub type PlayerStates = BTreeMap<u64, QuantizedMovement>;
+
+/// Movements of the other players sent by the server in a fixed interval.
+///
+/// To save bandwidth a snapshot only contains the players which changed since a baseline, an earlier snapshot
+/// the game acknowledged. Keyframes have no baseline and contain all players.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovedPlayersSnapshot {
+    /// Increased by one for every snapshot sent by the server, used to drop stale snapshots.
+    pub tick: u32,
+    /// Time since the server started when the snapshot was taken. Used by the game to place the snapshots
+    /// on a common timeline, independent of when they arrived.
+    pub server_time: Duration,
+    /// Tick of the snapshot the changes are relative to. `None` for keyframes.
+    pub baseline_tick: Option<u32>,
+    /// Players which joined or moved since the baseline.
+    pub changed: PlayerStates,
+    /// Ids of the players which left since the baseline.
+    pub removed: Vec<u64>,
+}
+
+impl MovedPlayersSnapshot {
+    /// Creates the snapshot of the `current` movements. Only contains the changes since the `baseline`,
+    /// given as its tick and movements, or all movements if there is none.
+    pub fn new(
+        tick: u32,
+        server_time: Duration,
+        baseline: Option<(u32, &PlayerStates)>,
+        current: &PlayerStates,
+    ) -> Self {
+        let Some((baseline_tick, baseline)) = baseline else {
+            return MovedPlayersSnapshot {
+                tick,
+                server_time,
+                baseline_tick: None,
+                changed: current.clone(),
+                removed: Vec::new(),
+            };
+        };
+
+        let changed = current
+            .iter()
+            .filter(|(id, movement)| baseline.get(id) != Some(movement))
+            .map(|(id, movement)| (*id, *movement))
+            .collect();
+        let removed = baseline
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .copied()
+            .collect();
+        MovedPlayersSnapshot {
+            tick,
+            server_time,
+            baseline_tick: Some(baseline_tick),
+            changed,
+            removed,
+        }
+    }
+
+    /// Restores the movements of all players by applying the changes to the movements of the baseline.
+    /// Returns `None` if the snapshot has a baseline, but it is not given.
+    pub fn apply(&self, baseline: Option<&PlayerStates>) -> Option<PlayerStates> {
+        let mut states = match self.baseline_tick {
+            Some(_) => baseline?.clone(),
+            None => PlayerStates::new(),
+        };
+        for id in &self.removed {
+            states.remove(id);
+        }
+        states.extend(self.changed.iter().map(|(id, movement)| (*id, *movement)));
+        Some(states)
+    }
+}
+
+/// Sent by a player to join the game. The name is shown to the other players
+/// and has to be accepted by the server, otherwise the join is rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub name: String,
+    pub movement: PlayerMovement,
+    /// Token of the previous session when reconnecting. If the server still knows the session the player
+    /// gets back the same id, name and current run.
+    pub session_token: Option<String>,
+}
+
+/// Sent by a client which wants to watch instead of play. The name and the session token of the last session
+/// are only used to refuse banned clients, spectators are not shown to anyone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpectateRequest {
+    pub name: String,
+    pub session_token: Option<String>,
+}
+
+/// Sent by the server when the player joined the game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Id of the player shown to the other players.
+    pub player_id: u64,
+    /// Token to resume the session after the connection was lost.
+    pub session_token: String,
+    /// If a previous session was resumed.
+    pub resumed: bool,
+}
+
+/// Id and display name of a player currently in the game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RosterEntry {
+    pub id: u64,
+    pub name: String,
+}
+
+/// Sent by a player to create a room. The player enters the room right away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRoomRequest {
+    pub name: String,
+    /// Private rooms can only be joined with the join code.
+    pub private: bool,
+}
+
+/// Sent by a player to enter a room from the [`ServerMessage::RoomList`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRoomRequest {
+    pub room_id: u64,
+    /// Needed to join a private room, ignored for public rooms.
+    pub join_code: Option<String>,
+}
+
+/// A room as shown in the list of rooms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub id: u64,
+    pub name: String,
+    /// Number of players currently in the room.
+    pub players: u32,
+    pub private: bool,
+}
+
+/// Sent by the server when the player entered a room, including the lobby every player starts in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinedRoom {
+    pub id: u64,
+    pub name: String,
+    /// Code the other players need to join, if the room is private.
+    pub join_code: Option<String>,
+}
+
+/// Place of a player in a race, sent in the order the players crossed the finish line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RacePlacing {
+    pub player_id: u64,
+    pub player_name: String,
+    /// Time from the start of the race until the player finished, measured by the server.
+    pub time: Duration,
+}
+
+/// A chat message of a player, forwarded by the server to all players in the same room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub player_id: u64,
+    pub player_name: String,
+    /// The text after the server applied its word filter.
+    pub text: String,
+}
+
+/// The best time for a level. Levels are identified by their LDtk level iid.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Highscore {
-    pub time_in_seconds: u64,
+    pub level_iid: String,
+    pub time: Duration,
+}
+
+/// A single finish on the leaderboard of a level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub player_name: String,
+    pub time: Duration,
+    /// Seconds since the unix epoch.
+    pub achieved_at: u64,
+}
+
+/// The best finishes of a level, sorted from fastest to slowest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub level_iid: String,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// Sent to all players when the server is about to stop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownInfo {
+    /// Why the server stops, meant to be shown to the player.
+    pub reason: String,
+    /// Time after which the server is expected to be back. `None` if it does not restart on its own.
+    pub restart_in: Option<Duration>,
+}
+
+/// Version of the protocol spoken between game and server. Has to be increased whenever a message is changed
+/// in a way an older game or server can not read anymore.
+pub const PROTOCOL_VERSION: u32 = 11;
+
+/// Exchanged right after the connection was established, so game and server can check if they understand each other.
+/// The layout of this struct must never change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    /// Name and version of the sending build, e.g. `game 0.1.0`.
+    pub build: String,
 }
 
 /// Messages sent from the player to the server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PlayerMessage {
+    /// First message of every game after connecting. Must stay the first variant, so it can be read by every version.
+    Handshake(Handshake),
     Ping,
-    JoinGame(PlayerMovement),
-    PlayerMoved(PlayerMovement),
-    RequestPossibleHighscore(Highscore),
+    JoinGame(JoinRequest),
+    /// Joins the lobby as a spectator instead of a player. Spectators see the ghosts of the room they are in,
+    /// but are not shown to anyone and can only join and leave rooms.
+    Spectate(SpectateRequest),
+    /// Sent on the unreliable channel, so it may be lost or arrive out of order.
+    PlayerMoved(MovementUpdate),
+    /// Sent when the player starts a run, including restarts after hitting a trap or finishing.
+    RunStarted,
+    /// Sent when the player reaches the finish line. Contains the level and the time measured by the
+    /// client, the server measures the official time itself.
+    RunFinished(Highscore),
+    /// Requests the leaderboard of the level with the given iid.
+    RequestLeaderboard(String),
+    /// Creates a room and moves the player into it.
+    CreateRoom(CreateRoomRequest),
+    /// Requests the list of all rooms, answered with a [`ServerMessage::RoomList`].
+    ListRooms,
+    JoinRoom(JoinRoomRequest),
+    /// Moves the player back to the lobby.
+    LeaveRoom,
+    /// Marks the player as ready for the next race in the room, or not ready anymore.
+    SetRaceReady(bool),
+    /// A chat message to all players in the room.
+    Chat(String),
     LeaveGame,
 }
 
 /// Messages sent from the server to the player.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
+    /// Answer to a compatible [`PlayerMessage::Handshake`], contains the handshake of the server.
+    /// Must stay the first variant, so it can be read by every version.
+    HandshakeAccepted(Handshake),
+    /// Answer to an incompatible [`PlayerMessage::Handshake`]. Contains the reason, meant to be shown to the player.
+    /// Must stay the second variant, so it can be read by every version.
+    HandshakeRejected(String),
     Pong,
     InformAboutHighscore(Highscore),
-    UpdateMovedPlayers(Vec<PlayerMovedUpdate>),
+    /// Sent on the unreliable channel, so it may be lost or arrive out of order.
+    UpdateMovedPlayers(MovedPlayersSnapshot),
+    Leaderboard(Leaderboard),
+    /// The run time measured by the server for the last finished run of the player.
+    RunTimeConfirmed(Highscore),
+    /// Sent when the player joined the game or resumed a session.
+    JoinAccepted(SessionInfo),
+    /// Sent when the join request was rejected, e.g. due to an invalid name. Contains the reason.
+    JoinRejected(String),
+    /// All players currently in the room of the player. Sent whenever a player joins or leaves the room.
+    PlayerRoster(Vec<RosterEntry>),
+    /// Answer to [`PlayerMessage::ListRooms`]. The lobby comes first.
+    RoomList(Vec<RoomInfo>),
+    /// Sent when the player entered a room. Followed by the highscores of the room.
+    RoomJoined(JoinedRoom),
+    /// Sent when creating or joining a room was rejected, e.g. due to a wrong join code. Contains the reason.
+    RoomRejected(String),
+    /// Ids of the players in the room who are ready for the next race. Sent whenever a player readies up or down.
+    RaceReady(Vec<u64>),
+    /// All players in the room are ready. The race starts at the contained time of the server, measured like
+    /// [`MovedPlayersSnapshot::server_time`]. Until then the players wait at the start.
+    RaceCountdown(Duration),
+    /// The finishing order of the current race so far. Sent whenever a player crossed the finish line.
+    RaceResults(Vec<RacePlacing>),
+    /// A chat message of a player in the room, including the own ones.
+    ChatBroadcast(ChatMessage),
+    /// Sent when a chat message was not forwarded, e.g. because it is too long or the player sends too many.
+    /// Contains the reason.
+    ChatRejected(String),
+    /// A message of the server operator to all players.
+    Announcement(String),
+    /// The highscore of the level with the given iid was removed by the server operator.
+    HighscoreRemoved(String),
+    /// Sent right before the server closes the connection of the player, who should not reconnect on its own.
+    /// Contains the reason, e.g. that the player was banned.
+    Kicked(String),
+    /// The server is about to stop and will close the connection shortly. Reconnecting is fine, as the server
+    /// may restart.
+    ServerShuttingDown(ShutdownInfo),
+}
+
+/// Number of expected messages after which the packet loss is calculated again.
+const PACKET_LOSS_WINDOW: u32 = 100;
+
+/// Keeps track of the sequence numbers of received messages. Drops messages which are older than or as old as
+/// the latest one and measures how many messages got lost.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    latest: Option<u32>,
+    expected: u32,
+    received: u32,
+    packet_loss: f32,
+}
+
+impl SequenceTracker {
+    /// Returns `true` if the message with the given sequence number is newer than all messages before.
+    /// Stale and duplicated messages return `false` and should be dropped.
+    pub fn accept(&mut self, sequence: u32) -> bool {
+        let skipped = match self.latest {
+            Some(latest) if sequence <= latest => return false,
+            Some(latest) => sequence - latest,
+            None => 1,
+        };
+        self.latest = Some(sequence);
+        self.expected = self.expected.saturating_add(skipped);
+        self.received += 1;
+
+        if self.expected >= PACKET_LOSS_WINDOW {
+            self.packet_loss = 1.0 - self.received as f32 / self.expected as f32;
+            self.expected = 0;
+            self.received = 0;
+        }
+        true
+    }
+
+    /// Share of the messages lost during the last measurement window, between `0.0` and `1.0`.
+    /// Messages which arrived too late to be used count as lost.
+    pub fn packet_loss(&self) -> f32 {
+        self.packet_loss
+    }
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_sequence_tracker_drops_stale_messages_and_measures_loss() {
+    let mut tracker = SequenceTracker::default();
+    assert!(tracker.accept(1));
+    assert!(tracker.accept(3));
+    assert!(!tracker.accept(2), "reordered message is accepted");
+    assert!(!tracker.accept(3), "duplicated message is accepted");
+
+    // Every tenth message gets lost
+    let mut tracker = SequenceTracker::default();
+    for sequence in 1..=100 {
+        if sequence % 10 != 5 {
+            tracker.accept(sequence);
+        }
+    }
+    assert!((tracker.packet_loss() - 0.1).abs() < 0.001);
+}
+
+#[test]
+fn test_snapshot_only_contains_changes_since_baseline() {
+    let movement = |x: f32| {
+        QuantizedMovement::from(&PlayerMovement {
+            velocity_x: 0.0,
+            velocity_y: 0.0,
+            translation_x: x,
+            translation_y: 10.0,
+        })
+    };
+    let baseline = PlayerStates::from([(1, movement(1.0)), (2, movement(2.0)), (3, movement(3.0))]);
+    let current = PlayerStates::from([(1, movement(1.0)), (2, movement(2.5)), (4, movement(4.0))]);
+
+    let snapshot = MovedPlayersSnapshot::new(8, Duration::ZERO, Some((7, &baseline)), &current);
+    assert_eq!(snapshot.changed.keys().collect::<Vec<_>>(), vec![&2, &4]);
+    assert_eq!(snapshot.removed, vec![3]);
+    assert_eq!(snapshot.apply(Some(&baseline)), Some(current));
+    assert_eq!(snapshot.apply(None), None);
+}
+
+#[test]
+fn test_quantized_movement_covers_levels() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../game/assets/jump_n_run.ldtk");
+    let project: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+
+    for level in project["levels"].as_array().unwrap() {
+        // Players can stand at the edges of a level and fall out of it at the bottom
+        let width = level["pxWid"].as_f64().unwrap() as f32;
+        let height = level["pxHei"].as_f64().unwrap() as f32;
+        for (x, y) in [(width + 64.0, height + 64.0), (-64.0, -64.0)] {
+            let movement = PlayerMovement {
+                velocity_x: 0.0,
+                velocity_y: 0.0,
+                translation_x: x,
+                translation_y: y,
+            };
+            let restored = PlayerMovement::from(QuantizedMovement::from(&movement));
+            assert_eq!((restored.translation_x, restored.translation_y), (x, y));
+        }
+    }
 }
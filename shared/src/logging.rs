@@ -0,0 +1,98 @@
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::PathBuf,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, prelude::*, EnvFilter};
+
+/// Filter used when the `RUST_LOG` environment variable is not set. Same as the default of bevy.
+pub const DEFAULT_LOG_FILTER: &str = "info,wgpu=error,naga=warn";
+
+/// How log events are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable text, one line per event.
+    #[default]
+    Pretty,
+    /// One JSON object per event, including the fields of the spans the event happened in.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim().to_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "log format `{}` is unknown, use `pretty` or `json`",
+                input
+            )),
+        }
+    }
+}
+
+/// Where and how the log events of the server or the game are written.
+#[derive(Debug, Clone, Default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// File the events are appended to. `None` writes them to stderr.
+    pub file: Option<PathBuf>,
+}
+
+/// Installs the global subscriber for the `tracing` events. Replaces the `LogPlugin` of bevy, which has
+/// to be left out. The levels are filtered with the `RUST_LOG` environment variable, e.g. `RUST_LOG=debug`,
+/// and default to [`DEFAULT_LOG_FILTER`]. Messages of the `log` crate used by some dependencies are
+/// forwarded as well.
+///
+/// Fails if the log file can not be opened or a subscriber is already installed.
+pub fn init_logging(config: &LogConfig) -> io::Result<()> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+
+    let writer = match &config.file {
+        Some(path) => {
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(io::stderr),
+    };
+
+    let layer = match config.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .with_ansi(config.file.is_none())
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .try_init()
+        .map_err(io::Error::other)
+}
+
+//
+// ------> Tests <------ //
+//
+
+#[test]
+fn test_log_format_is_parsed() {
+    assert_eq!("json".parse(), Ok(LogFormat::Json));
+    assert_eq!(" Pretty ".parse(), Ok(LogFormat::Pretty));
+    assert!("yaml".parse::<LogFormat>().is_err());
+}